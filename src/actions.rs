@@ -0,0 +1,142 @@
+use std::{sync::OnceLock, time::Instant};
+
+use smithay_client_toolkit::seat::pointer::{BTN_LEFT, BTN_MIDDLE, BTN_RIGHT};
+use wayland_client::protocol::wl_pointer::ButtonState;
+
+use crate::pointer::PointerBackend;
+
+/// What happens once the pointer has been moved to the selected position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Left,
+    Right,
+    Middle,
+    Double,
+    Triple,
+    None,
+}
+
+impl Action {
+    pub const NAMES: &str = "left, right, middle, double, triple or none";
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "left" => Action::Left,
+            "right" => Action::Right,
+            "middle" => Action::Middle,
+            "double" => Action::Double,
+            "triple" => Action::Triple,
+            "none" => Action::None,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Left => "left",
+            Action::Right => "right",
+            Action::Middle => "middle",
+            Action::Double => "double",
+            Action::Triple => "triple",
+            Action::None => "none",
+        }
+    }
+
+    /// The button to click and how many times to click it.
+    fn clicks(self) -> Option<(u32, u32)> {
+        match self {
+            Action::Left => Some((BTN_LEFT, 1)),
+            Action::Right => Some((BTN_RIGHT, 1)),
+            Action::Middle => Some((BTN_MIDDLE, 1)),
+            Action::Double => Some((BTN_LEFT, 2)),
+            Action::Triple => Some((BTN_LEFT, 3)),
+            Action::None => None,
+        }
+    }
+
+    /// Clicks at the current pointer position. Repeated clicks are sent
+    /// right after each other so they register as a double or triple click.
+    pub fn perform(self, pointer: &mut dyn PointerBackend) {
+        let Some((button, count)) = self.clicks() else {
+            return;
+        };
+        for _ in 0..count {
+            pointer.button(button, ButtonState::Pressed);
+            pointer.frame();
+            pointer.button(button, ButtonState::Released);
+            pointer.frame();
+        }
+    }
+}
+
+/// Milliseconds since the first call, for the `time` argument of virtual
+/// pointer and keyboard requests.
+pub fn timestamp() -> u32 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_millis() as u32
+}
+
+/// Points on the line from `from` to `to`, excluding `from` and including
+/// `to`, roughly `step` pixels apart.
+pub fn interpolate(from: (i32, i32), to: (i32, i32), step: u32) -> Vec<(i32, i32)> {
+    let dx = to.0 as f64 - from.0 as f64;
+    let dy = to.1 as f64 - from.1 as f64;
+    let steps = ((dx.hypot(dy) / step as f64).ceil() as u32).max(1);
+    (1..=steps)
+        .map(|i| {
+            let t = i as f64 / steps as f64;
+            (
+                (from.0 as f64 + dx * t).round() as i32,
+                (from.1 as f64 + dy * t).round() as i32,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::interpolate;
+
+    #[test]
+    fn interpolates_equal_endpoints() {
+        // Still moves there once, the drag has to end somewhere
+        assert_eq!(interpolate((5, -5), (5, -5), 20), vec![(5, -5)]);
+    }
+
+    #[test]
+    fn interpolates_diagonals() {
+        assert_eq!(
+            interpolate((0, 0), (30, 40), 20),
+            vec![(10, 13), (20, 27), (30, 40)]
+        );
+        assert_eq!(
+            interpolate((30, 40), (0, 0), 20),
+            vec![(20, 27), (10, 13), (0, 0)]
+        );
+    }
+
+    #[test]
+    fn interpolates_at_step_boundaries() {
+        assert_eq!(interpolate((0, 0), (40, 0), 20), vec![(20, 0), (40, 0)]);
+        assert_eq!(
+            interpolate((0, 0), (41, 0), 20),
+            vec![(14, 0), (27, 0), (41, 0)]
+        );
+        assert_eq!(interpolate((0, 0), (0, -19), 20), vec![(0, -19)]);
+        assert_eq!(interpolate((0, 0), (0, 20), 20), vec![(0, 20)]);
+        assert_eq!(interpolate((0, 0), (0, 21), 20), vec![(0, 11), (0, 21)]);
+    }
+
+    #[test]
+    fn interpolated_points_are_at_most_a_step_apart() {
+        let (from, to) = ((-1920, 300), (2559, -17));
+        let points = interpolate(from, to, 20);
+        let mut previous = from;
+        for &point in &points {
+            let distance = ((point.0 - previous.0) as f64).hypot((point.1 - previous.1) as f64);
+            assert!(distance <= 20.5, "{previous:?} to {point:?}");
+            previous = point;
+        }
+        assert_eq!(points.last(), Some(&to));
+    }
+}
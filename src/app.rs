@@ -1,31 +1,43 @@
-use std::{
-    collections::{HashMap, HashSet},
-    i32,
-    os::{fd::AsFd, linux::raw::stat},
-};
+use std::{collections::HashMap, env, mem, thread, time::Duration};
 
-use smithay_client_toolkit::seat::pointer::BTN_LEFT;
+use nix::time::{ClockId, clock_gettime};
 use wayland_client::{
-    Dispatch, EventQueue, WEnum,
+    Dispatch, EventQueue, Proxy, QueueHandle, WEnum,
     protocol::{
-        wl_buffer::WlBuffer,
-        wl_callback::WlCallback,
+        wl_buffer::{self, WlBuffer},
+        wl_callback::{self, WlCallback},
         wl_compositor::WlCompositor,
-        wl_keyboard::{KeyState, WlKeyboard},
+        wl_keyboard::{self, KeyState, KeymapFormat, WlKeyboard},
         wl_output::{Mode, WlOutput},
-        wl_pointer::ButtonState,
+        wl_pointer::{self, WlPointer},
+        wl_region::WlRegion,
         wl_registry::WlRegistry,
-        wl_seat::WlSeat,
+        wl_seat::{self, WlSeat},
         wl_shm::{self, Format, WlShm},
         wl_shm_pool::WlShmPool,
-        wl_surface::WlSurface,
+        wl_surface::{self, WlSurface},
+    },
+};
+use wayland_protocols::wp::{
+    fractional_scale::v1::client::{
+        wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+        wp_fractional_scale_v1::{self, WpFractionalScaleV1},
     },
+    presentation_time::client::{
+        wp_presentation::{self, WpPresentation},
+        wp_presentation_feedback::{self, WpPresentationFeedback},
+    },
+    viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
 };
 use wayland_protocols::xdg::shell::client::{
     xdg_surface::XdgSurface,
     xdg_toplevel::{self, XdgToplevel},
     xdg_wm_base::XdgWmBase,
 };
+use wayland_protocols::xdg::xdg_output::zv1::client::{
+    zxdg_output_manager_v1::ZxdgOutputManagerV1,
+    zxdg_output_v1::{self, ZxdgOutputV1},
+};
 use wayland_protocols_wlr::{
     layer_shell::v1::client::{
         zwlr_layer_shell_v1::{Layer, ZwlrLayerShellV1},
@@ -38,12 +50,39 @@ use wayland_protocols_wlr::{
 };
 
 use crate::{
-    app,
-    buf_utils::{Surface, allocate_shm_buffer},
-    output_utils::Output,
-    position_selector::{FinalSelector, SelectorState},
+    actions::timestamp,
+    backend::{Backend, OutputInfo, Render},
+    buf_utils::Surface,
+    config::Config,
+    keyboard_utils::{Keyboard, KeyboardEvent, Modifiers},
+    output_utils::{Output, OutputTarget, UNKNOWN_OUTPUT, layout_extents},
+    pointer::{BackendKind, Extents, PointerBackend},
+    position_selector::{FinalSelector, InitialSelector, SelectorState},
+    virtual_keyboard::{
+        client::{
+            zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1,
+            zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1,
+        },
+        text_keymap, upload_keymap,
+    },
 };
 
+/// How long to wait for the pointer to enter one of the overlays before
+/// giving up on finding the output it is on.
+const POINTER_ENTER_ROUNDTRIPS: usize = 3;
+
+/// How long to wait for the compositor to say which output it put an
+/// overlay on, when it picked the output itself.
+const SURFACE_ENTER_ROUNDTRIPS: usize = 3;
+
+/// Time between the keys of `type_text`, for applications that drop keys
+/// arriving all at once.
+const TYPE_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Set to print how long it takes from a key press to the frame showing it
+/// on screen, as reported by `wp_presentation`.
+const LATENCY_VAR: &str = "NOMOUSE_LATENCY";
+
 #[derive(Default, Debug)]
 pub struct AppData {
     pub compositor: Option<WlCompositor>,
@@ -53,79 +92,583 @@ pub struct AppData {
     pub layer_shell: Option<ZwlrLayerShellV1>,
     pub seat: Option<WlSeat>,
     pub keyboard: Option<WlKeyboard>,
+    pub keyboard_state: Keyboard,
+    /// Key repeat rate and delay, when the compositor sent them
+    pub repeat_info: Option<(i32, i32)>,
     pub outputs: HashMap<u32, Output>,
-    pub surface: Option<Surface>,
-    pub layer_surface: Option<ZwlrLayerSurfaceV1>,
+    /// For the logical sizes of the outputs, which can't be told from the
+    /// mode with fractional scales
+    pub xdg_output_manager: Option<ZxdgOutputManagerV1>,
+    pub surfaces: HashMap<u32, Surface>,
     pub virtual_pointer_manager: Option<ZwlrVirtualPointerManagerV1>,
-    pub pointer: Option<ZwlrVirtualPointerV1>,
-    pub adding_output: Option<u32>,
-    pub selected_output: Option<u32>,
-    pub procesed_keypress_serials: HashSet<u32>,
-    pub selector: Option<SelectorState>,
-    pub do_click: bool,
+    pub viewporter: Option<WpViewporter>,
+    pub fractional_scale_manager: Option<WpFractionalScaleManagerV1>,
+    pub presentation: Option<WpPresentation>,
+    /// The clock of the presentation timestamps, announced after binding
+    presentation_clock: Option<ClockId>,
+    /// When the last key that changed the overlay was pressed, on the
+    /// presentation clock, until a frame showing the change is committed
+    pub key_time: Option<Duration>,
+    /// Kept by `keep_pointer` between the sessions of the daemon, along with
+    /// the kind it was asked for
+    pointer: Option<(BackendKind, Box<dyn PointerBackend>)>,
+    pub virtual_keyboard_manager: Option<ZwpVirtualKeyboardManagerV1>,
+    /// Created the first time modifiers are held or text is typed, see
+    /// `virtual_keyboard`
+    pub virtual_keyboard: Option<ZwpVirtualKeyboardV1>,
+    /// The keymap last uploaded to `virtual_keyboard`
+    virtual_keymap: String,
+    /// The serial of the last key event, which is dropped when it comes
+    /// again
+    last_key_serial: Option<u32>,
+    /// Waiting for the session to handle them
+    pub keyboard_events: Vec<KeyboardEvent>,
+    /// Where the overlay of the current selection is shown
+    pub target: OutputTarget,
+    pub wl_pointer: Option<WlPointer>,
+    /// The overlay the pointer is on, for `OutputTarget::Pointer`
+    pub entered_surface: Option<WlSurface>,
+    /// Set when an output came or went or a surface changed size or scale
+    /// after the selection started, which invalidates the selector. The
+    /// selection is cancelled once there are no surfaces left
+    pub layout_changed: bool,
 }
 
 impl AppData {
-    pub fn init_that_shit(&mut self, event_queue: &mut EventQueue<Self>) {
+    /// Connects to the compositor named by the environment and waits for
+    /// its globals and outputs.
+    pub fn connect() -> Result<(Self, EventQueue<Self>), String> {
+        let conn = wayland_client::Connection::connect_to_env()
+            .map_err(|err| format!("can't connect to the compositor: {err}"))?;
+        Self::connect_to(conn)
+    }
+
+    /// Waits for the globals and outputs of the compositor on the other
+    /// end of `conn`.
+    fn connect_to(conn: wayland_client::Connection) -> Result<(Self, EventQueue<Self>), String> {
+        let display = conn.display();
+        let mut event_queue: EventQueue<Self> = conn.new_event_queue();
         let qh = event_queue.handle();
-        loop {
-            println!("roundtripping");
-            event_queue.roundtrip(self).unwrap();
-            match (&self.compositor, &self.layer_shell, &self.surface) {
-                (Some(compositor), Some(layer_shell), None) => {
-                    let wl_surface = compositor.create_surface(&qh, ());
-                    let layer_surface = layer_shell.get_layer_surface(
-                        &wl_surface,
-                        None,
-                        Layer::Overlay,
-                        "gtk-layer-shell".into(),
-                        &qh,
-                        (),
-                    );
-                    layer_surface.set_anchor(Anchor::all());
-                    layer_surface.set_exclusive_zone(-1);
-                    layer_surface.set_keyboard_interactivity(KeyboardInteractivity::Exclusive);
-                    wl_surface.commit();
-                    self.surface = Some(Surface {
-                        width: 1,
-                        height: 1,
-                        wl_surface: wl_surface,
-                        buf: allocate_shm_buffer(4),
-                        wl_buf: None,
-                    });
-                    self.layer_surface = Some(layer_surface);
-                    println!("Initialized surface");
+        let _registry = display.get_registry(&qh, ());
+        let mut app = AppData {
+            ..Default::default()
+        };
+        app.init_that_shit(&mut event_queue)?;
+        Ok((app, event_queue))
+    }
+
+    /// Roundtrips until the globals are bound and every output has sent
+    /// its properties. Fails naming the global the compositor lacks.
+    pub fn init_that_shit(&mut self, event_queue: &mut EventQueue<Self>) -> Result<(), String> {
+        // The globals are announced by the first roundtrip, the properties
+        // of the outputs bound then by the second
+        for _ in 0..2 {
+            event_queue
+                .roundtrip(self)
+                .map_err(|err| format!("wayland connection: {err}"))?;
+        }
+        let required = [
+            ("wl_compositor", self.compositor.is_some()),
+            ("wl_shm", self.shm.is_some()),
+            ("zwlr_layer_shell_v1", self.layer_shell.is_some()),
+        ];
+        if let Some((global, _)) = required.iter().find(|(_, bound)| !bound) {
+            return Err(format!("the compositor doesn't support {global}"));
+        }
+        if self.outputs.is_empty() {
+            return Err("the compositor has no outputs (wl_output)".into());
+        }
+        if let Some(output) = self.outputs.values().find(|output| !output.done) {
+            return Err(format!(
+                "output {} didn't send its properties",
+                output.name.as_deref().unwrap_or("without a name")
+            ));
+        }
+        Ok(())
+    }
+
+    /// Shows an overlay on every output and waits until each of them has
+    /// been configured.
+    pub fn create_surfaces(
+        &mut self,
+        event_queue: &mut EventQueue<Self>,
+        target: &OutputTarget,
+    ) -> Result<(), String> {
+        let qh = event_queue.handle();
+        self.target = target.clone();
+        self.entered_surface = None;
+        match target {
+            // The compositor picks, we find out which one it was on enter
+            OutputTarget::Focused => self.create_surface(None, &qh),
+            // Shown everywhere until the pointer enters one of them
+            OutputTarget::All | OutputTarget::Pointer => {
+                let names: Vec<u32> = self.outputs.keys().copied().collect();
+                for name in names {
+                    self.create_surface(Some(name), &qh);
                 }
-                _ => {}
             }
-            if self
-                .surface
-                .as_ref()
-                .and_then(|s| s.wl_buf.as_ref())
-                .is_some()
-            {
-                self.surface.as_ref().unwrap().wl_surface.attach(
-                    Some(&self.surface.as_ref().unwrap().wl_buf.as_ref().unwrap()),
-                    0,
-                    0,
-                );
-                self.surface.as_ref().unwrap().wl_surface.commit();
-
-                let (selected_output_name, selected_output) = self
+            OutputTarget::Name(wanted) => {
+                let name = self
                     .outputs
                     .iter()
-                    .find(|(_, output)| match output.flags {
-                        Some(WEnum::Value(mode)) => mode.contains(Mode::Current),
-                        _ => false,
-                    })
-                    .unwrap();
-
-                self.selected_output = Some(selected_output_name.clone());
+                    .find(|(_, output)| output.matches(wanted))
+                    .map(|(name, _)| *name)
+                    .ok_or_else(|| format!("no output named {wanted:?}"))?;
+                self.create_surface(Some(name), &qh);
+            }
+        }
+        while !self
+            .surfaces
+            .values()
+            .all(|surface| surface.is_configured())
+        {
+            event_queue.roundtrip(self).unwrap();
+        }
+        for surface in self.surfaces.values_mut() {
+            // Mapped blank, still dirty so the first frame is drawn next
+            if let Some((wl_buffer, buf)) = surface.buffers.as_mut().unwrap().acquire(&qh) {
+                buf.fill(0);
+                surface.wl_surface.attach(Some(&wl_buffer), 0, 0);
+            }
+            surface.wl_surface.commit();
+        }
+        // The preferred fractional scale and the output of a surface are
+        // only sent once the surfaces are mapped, pick them up before the
+        // selector is laid out
+        event_queue.roundtrip(self).unwrap();
+        for _ in 0..SURFACE_ENTER_ROUNDTRIPS {
+            if !self.surfaces.contains_key(&UNKNOWN_OUTPUT) {
+                break;
+            }
+            event_queue.roundtrip(self).unwrap();
+        }
+        if *target == OutputTarget::Pointer {
+            self.keep_pointer_surface(event_queue);
+        }
+        Ok(())
+    }
 
+    /// Keeps only the overlay the pointer entered. Compositors send the
+    /// enter event when a surface shows up under the pointer, but may wait
+    /// for the pointer to move, in which case all of them are kept.
+    fn keep_pointer_surface(&mut self, event_queue: &mut EventQueue<Self>) {
+        for _ in 0..POINTER_ENTER_ROUNDTRIPS {
+            if self.entered_surface.is_some() {
                 break;
+            }
+            event_queue.roundtrip(self).unwrap();
+        }
+        if let Some(entered) = &self.entered_surface
+            && let Some(&name) = self
+                .surfaces
+                .iter()
+                .find(|(_, surface)| surface.wl_surface == *entered)
+                .map(|(name, _)| name)
+        {
+            let others: Vec<u32> = self
+                .surfaces
+                .keys()
+                .copied()
+                .filter(|other| *other != name)
+                .collect();
+            for other in others {
+                self.surfaces.remove(&other).unwrap().destroy();
+            }
+        }
+        // Only needed the pointer to find out where it is
+        let qh = event_queue.handle();
+        for surface in self.surfaces.values() {
+            surface.pass_pointer_through(self.compositor.as_ref().unwrap(), &qh);
+            surface.wl_surface.commit();
+        }
+        event_queue.roundtrip(self).unwrap();
+    }
+
+    /// Shows an overlay on `output`, or on the output the compositor picks
+    /// when `None`. It can be drawn on once the compositor has configured it.
+    fn create_surface(&mut self, output: Option<u32>, qh: &QueueHandle<Self>) {
+        let compositor = self.compositor.as_ref().unwrap();
+        let layer_shell = self.layer_shell.as_ref().unwrap();
+        let output = output.map(|name| (name, &self.outputs[&name]));
+        let wl_surface = compositor.create_surface(qh, ());
+        let viewport = self
+            .viewporter
+            .as_ref()
+            .map(|viewporter| viewporter.get_viewport(&wl_surface, qh, ()));
+        // Fractional scales can only be applied through a viewport
+        let fractional_scale = self
+            .fractional_scale_manager
+            .as_ref()
+            .filter(|_| viewport.is_some())
+            .map(|manager| manager.get_fractional_scale(&wl_surface, qh, wl_surface.clone()));
+        let layer_surface = layer_shell.get_layer_surface(
+            &wl_surface,
+            output.map(|(_, output)| &output.wl_output),
+            Layer::Overlay,
+            "gtk-layer-shell".into(),
+            qh,
+            wl_surface.clone(),
+        );
+        layer_surface.set_anchor(Anchor::all());
+        layer_surface.set_exclusive_zone(-1);
+        layer_surface.set_keyboard_interactivity(KeyboardInteractivity::Exclusive);
+        let surface = Surface {
+            output: output.map_or(UNKNOWN_OUTPUT, |(name, _)| name),
+            width: 1,
+            height: 1,
+            // Until the compositor tells us the fractional scale
+            scale: output
+                .and_then(|(_, output)| output.scale)
+                .unwrap_or(1)
+                .max(1) as f64,
+            wl_surface,
+            layer_surface,
+            viewport,
+            fractional_scale,
+            buffers: None,
+            dirty: false,
+            frame_pending: false,
+        };
+        if self.target != OutputTarget::Pointer {
+            surface.pass_pointer_through(compositor, qh);
+        }
+        surface.wl_surface.commit();
+        self.surfaces.insert(surface.output, surface);
+    }
+
+    /// Takes down the overlay of an output that went away.
+    fn remove_surface(&mut self, name: u32) {
+        let Some(surface) = self.surfaces.remove(&name) else {
+            return;
+        };
+        surface.destroy();
+        self.layout_changed = true;
+    }
+
+    /// Builds a grid for every output, in buffer pixels of its surface. With
+    /// more than one output the first key picks the output, ordered left to
+    /// right. Fails when there are more outputs than hint characters.
+    pub fn create_selector(&self, config: &Config) -> Result<SelectorState, String> {
+        let hints = &config.hints;
+        // Surfaces whose output isn't known, or went away, have no place in
+        // the layout
+        let mut surfaces: Vec<(&Surface, &Output)> = self
+            .surfaces
+            .values()
+            .filter_map(|surface| Some((surface, self.outputs.get(&surface.output)?)))
+            .collect();
+        if surfaces.is_empty() {
+            return Err("the overlay isn't shown on any known output".to_string());
+        }
+        surfaces.sort_by_key(|(_, output)| {
+            let (x, y, _, _) = output.rect();
+            (x, y)
+        });
+        let grids = surfaces
+            .into_iter()
+            .map(|(surface, _)| {
+                InitialSelector::new(
+                    surface.output,
+                    hints,
+                    &config.grid,
+                    surface.buffer_width(),
+                    surface.buffer_height(),
+                )
+            })
+            .collect();
+        SelectorState::new(grids, &hints.alphabet)
+    }
+
+    /// The center of the selection in the global compositor space, or
+    /// `None` when its output went away.
+    pub fn global_position(&self, selector: &FinalSelector) -> Option<(i32, i32)> {
+        let (left, top, _, _) = self.outputs.get(&selector.output)?.rect();
+        // The selection is in buffer pixels
+        let scale = self
+            .surfaces
+            .get(&selector.output)
+            .map_or(1.0, |surface| surface.scale);
+        let x = left + ((selector.x + selector.width / 2) as f64 / scale) as i32;
+        let y = top + ((selector.y + selector.height / 2) as f64 / scale) as i32;
+        Some((x, y))
+    }
+
+    /// The extents of all outputs, which the pointer is moved within.
+    pub fn layout_extents(&self) -> Extents {
+        layout_extents(self.outputs.values().map(Output::rect))
+    }
+
+    /// Connects to the `kind` of pointer backend, or hands out the one kept
+    /// by `keep_pointer` when it is of that kind.
+    pub fn take_pointer(
+        &mut self,
+        kind: BackendKind,
+        qh: &QueueHandle<Self>,
+    ) -> Result<Box<dyn PointerBackend>, String> {
+        if let Some((kept, pointer)) = self.pointer.take() {
+            if kept == kind {
+                return Ok(pointer);
+            }
+            // Let go of the old one first, uinput devices would pile up
+            // otherwise
+            drop(pointer);
+        }
+        kind.connect(self, qh)
+    }
+
+    /// Keeps `pointer` for the next `take_pointer`, connecting can take a
+    /// while.
+    pub fn keep_pointer(&mut self, kind: BackendKind, pointer: Box<dyn PointerBackend>) {
+        self.pointer = Some((kind, pointer));
+    }
+
+    /// Has every overlay drawn again once the compositor is ready for its
+    /// next frame, after something it shows changed.
+    pub fn request_redraw(&mut self) {
+        for surface in self.surfaces.values_mut() {
+            surface.dirty = true;
+        }
+    }
+
+    /// The current time on the clock of `wp_presentation`, to compare the
+    /// presentation timestamps with.
+    fn presentation_time(&self) -> Option<Duration> {
+        let time = clock_gettime(self.presentation_clock?).ok()?;
+        Some(Duration::new(time.tv_sec() as u64, time.tv_nsec() as u32))
+    }
+
+    /// The virtual keyboard with `keymap` uploaded, created the first time
+    /// it is needed.
+    fn virtual_keyboard(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        keymap: String,
+    ) -> Result<&ZwpVirtualKeyboardV1, String> {
+        if self.virtual_keyboard.is_none() {
+            let manager = self
+                .virtual_keyboard_manager
+                .as_ref()
+                .ok_or("the compositor doesn't support zwp_virtual_keyboard_manager_v1")?;
+            let seat = self.seat.as_ref().ok_or("no seat")?;
+            self.virtual_keyboard = Some(manager.create_virtual_keyboard(seat, qh, ()));
+            self.virtual_keymap.clear();
+        }
+        let keyboard = self.virtual_keyboard.as_ref().unwrap();
+        if self.virtual_keymap != keymap {
+            upload_keymap(keyboard, &keymap)
+                .map_err(|err| format!("can't upload the keymap: {err}"))?;
+            self.virtual_keymap = keymap;
+        }
+        Ok(keyboard)
+    }
+
+    /// Holds down `held` on the virtual keyboard, so the clicks that follow
+    /// are e.g. ctrl+clicks. Does nothing when none are held.
+    pub fn press_held_modifiers(&mut self, qh: &QueueHandle<Self>, held: Modifiers) {
+        if held.is_empty() {
+            return;
+        }
+        let depressed = self.keyboard_state.modifier_mask(held);
+        let (locked, group) = self.keyboard_state.locked();
+        // The keymap of the real keyboard, so the masks mean the same
+        let keyboard = self
+            .keyboard_state
+            .keymap_string()
+            .ok_or_else(|| "the compositor didn't send a keymap".to_string())
+            .and_then(|keymap| self.virtual_keyboard(qh, keymap));
+        match keyboard {
+            Ok(keyboard) => keyboard.modifiers(depressed, 0, locked, group),
+            Err(err) => eprintln!("nomouse: clicking without {held}: {err}"),
+        }
+    }
+
+    /// Lets go of the modifiers held down by `press_held_modifiers`.
+    pub fn release_held_modifiers(&self, held: Modifiers) {
+        if let Some(keyboard) = &self.virtual_keyboard
+            && !held.is_empty()
+        {
+            let (locked, group) = self.keyboard_state.locked();
+            keyboard.modifiers(0, 0, locked, group);
+        }
+    }
+
+    /// Types `text` on the virtual keyboard, into whatever has the keyboard
+    /// focus.
+    pub fn type_text(
+        &mut self,
+        event_queue: &mut EventQueue<Self>,
+        text: &str,
+    ) -> Result<(), String> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        let (keymap, keycodes) = text_keymap(text)?;
+        self.virtual_keyboard(&event_queue.handle(), keymap)?;
+        // Give the compositor a chance to pass the keymap on before the
+        // keys that need it
+        event_queue.roundtrip(self).unwrap();
+        let keyboard = self.virtual_keyboard.as_ref().unwrap();
+        for keycode in keycodes {
+            keyboard.key(timestamp(), keycode, KeyState::Pressed.into());
+            keyboard.key(timestamp(), keycode, KeyState::Released.into());
+            event_queue.flush().unwrap();
+            thread::sleep(TYPE_INTERVAL);
+        }
+        Ok(())
+    }
+
+    pub fn destroy_surfaces(&mut self) {
+        for (_, surface) in self.surfaces.drain() {
+            surface.destroy();
+        }
+    }
+}
+
+/// `AppData` along with its event queue, which a `Session` runs on.
+pub struct Wayland<'a> {
+    pub app: &'a mut AppData,
+    pub event_queue: &'a mut EventQueue<AppData>,
+}
+
+impl<'a> Wayland<'a> {
+    pub fn new(app: &'a mut AppData, event_queue: &'a mut EventQueue<AppData>) -> Self {
+        Self { app, event_queue }
+    }
+}
+
+impl Backend for Wayland<'_> {
+    fn take_pointer(&mut self, kind: BackendKind) -> Result<Box<dyn PointerBackend>, String> {
+        self.app.take_pointer(kind, &self.event_queue.handle())
+    }
+
+    fn keep_pointer(&mut self, kind: BackendKind, pointer: Box<dyn PointerBackend>) {
+        self.app.keep_pointer(kind, pointer);
+    }
+
+    fn show(&mut self, target: &OutputTarget) -> Result<(), String> {
+        self.app.create_surfaces(self.event_queue, target)
+    }
+
+    fn hide(&mut self) {
+        self.app.destroy_surfaces();
+        self.event_queue.roundtrip(self.app).unwrap();
+    }
+
+    fn is_shown(&self) -> bool {
+        !self.app.surfaces.is_empty()
+    }
+
+    fn take_keyboard_events(&mut self) -> Vec<KeyboardEvent> {
+        mem::take(&mut self.app.keyboard_events)
+    }
+
+    fn take_layout_changed(&mut self) -> bool {
+        mem::take(&mut self.app.layout_changed)
+    }
+
+    fn create_selector(&self, config: &Config) -> Result<SelectorState, String> {
+        self.app.create_selector(config)
+    }
+
+    fn global_position(&self, selector: &FinalSelector) -> Option<(i32, i32)> {
+        self.app.global_position(selector)
+    }
+
+    fn layout_extents(&self) -> Extents {
+        self.app.layout_extents()
+    }
+
+    fn outputs(&self) -> Vec<OutputInfo> {
+        self.app
+            .outputs
+            .iter()
+            .map(|(&name, output)| OutputInfo {
+                id: name,
+                name: output.name.clone(),
+                rect: output.rect(),
+                shown: self.app.surfaces.contains_key(&name),
+            })
+            .collect()
+    }
+
+    fn repeat_info(&self) -> Option<(i32, i32)> {
+        self.app.repeat_info
+    }
+
+    fn press_held_modifiers(&mut self, held: Modifiers) {
+        self.app
+            .press_held_modifiers(&self.event_queue.handle(), held);
+    }
+
+    fn release_held_modifiers(&mut self, held: Modifiers) {
+        self.app.release_held_modifiers(held);
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<(), String> {
+        self.app.type_text(self.event_queue, text)
+    }
+
+    fn request_redraw(&mut self) {
+        self.app.request_redraw();
+    }
+
+    /// Commits the overlays that changed since their last frame, unless the
+    /// compositor hasn't asked for the next frame yet. The frame callback
+    /// ends up back here once it has.
+    fn draw(&mut self, mut render: Option<&mut Render>) {
+        let app = &mut *self.app;
+        if !app.surfaces.values().any(|surface| surface.dirty) {
+            // The key changed nothing
+            app.key_time = None;
+            return;
+        }
+        let qh = self.event_queue.handle();
+        for surface in app.surfaces.values_mut() {
+            // Not configured yet, or still waiting for the last frame
+            if !surface.dirty || surface.frame_pending || !surface.is_configured() {
+                continue;
+            }
+            let (width, height) = (surface.buffer_width(), surface.buffer_height());
+            // Drawn once the compositor releases one when they are all busy
+            let Some((wl_buffer, framebuf)) = surface.buffers.as_mut().unwrap().acquire(&qh) else {
+                continue;
             };
+            match render.as_mut() {
+                Some(render) => render(surface.output, framebuf, width, height, surface.scale),
+                None => framebuf.fill(0),
+            }
+            surface.wl_surface.attach(Some(&wl_buffer), 0, 0);
+            surface.wl_surface.damage(0, 0, i32::MAX, i32::MAX);
+            surface.wl_surface.frame(&qh, surface.wl_surface.clone());
+            // Once per key press, on the first overlay showing it
+            if let Some(key_time) = app.key_time.take()
+                && let Some(presentation) = &app.presentation
+                && env::var_os(LATENCY_VAR).is_some()
+            {
+                presentation.feedback(&surface.wl_surface, &qh, key_time);
+            }
+            surface.wl_surface.commit();
+            surface.dirty = false;
+            surface.frame_pending = true;
         }
     }
+
+    fn flush(&mut self) {
+        self.event_queue.flush().unwrap();
+    }
+
+    fn sync(&mut self) {
+        self.event_queue.roundtrip(self.app).unwrap();
+    }
+}
+
+fn find_surface<'a>(
+    surfaces: &'a mut HashMap<u32, Surface>,
+    wl_surface: &WlSurface,
+) -> Option<&'a mut Surface> {
+    surfaces
+        .values_mut()
+        .find(|surface| surface.wl_surface == *wl_surface)
 }
 
 impl Dispatch<WlRegistry, ()> for AppData {
@@ -133,17 +676,30 @@ impl Dispatch<WlRegistry, ()> for AppData {
         state: &mut Self,
         registry: &WlRegistry,
         event: <WlRegistry as wayland_client::Proxy>::Event,
-        data: &(),
-        conn: &wayland_client::Connection,
+        _data: &(),
+        _conn: &wayland_client::Connection,
         qhandle: &wayland_client::QueueHandle<Self>,
     ) {
-        dbg!(&event);
-        match event {
-            wayland_client::protocol::wl_registry::Event::Global {
-                name,
-                interface,
-                version,
-            } => match interface.as_str() {
+        if let wayland_client::protocol::wl_registry::Event::GlobalRemove { name } = event {
+            // Only outputs come and go in practice
+            if let Some(output) = state.outputs.remove(&name) {
+                if let Some(xdg_output) = &output.xdg_output {
+                    xdg_output.destroy();
+                }
+                if output.wl_output.version() >= 3 {
+                    output.wl_output.release();
+                }
+                state.remove_surface(name);
+            }
+            return;
+        }
+        if let wayland_client::protocol::wl_registry::Event::Global {
+            name,
+            interface,
+            version,
+        } = event
+        {
+            match interface.as_str() {
                 "wl_compositor" => {
                     let compositor = registry.bind::<WlCompositor, _, _>(name, 2, qhandle, ());
                     state.compositor = Some(compositor);
@@ -156,11 +712,27 @@ impl Dispatch<WlRegistry, ()> for AppData {
                         Some(registry.bind::<XdgWmBase, _, _>(name, version, qhandle, ()));
                 }
                 "wl_output" => {
-                    state.outputs.insert(
+                    let mut output =
+                        Output::new(registry.bind::<WlOutput, _, _>(name, version, qhandle, name));
+                    if let Some(manager) = &state.xdg_output_manager {
+                        output.xdg_output =
+                            Some(manager.get_xdg_output(&output.wl_output, qhandle, name));
+                    }
+                    state.outputs.insert(name, output);
+                }
+                "zxdg_output_manager_v1" => {
+                    let manager = registry.bind::<ZxdgOutputManagerV1, _, _>(
                         name,
-                        Output::new(registry.bind::<WlOutput, _, _>(name, version, qhandle, ())),
+                        version.min(3),
+                        qhandle,
+                        (),
                     );
-                    state.adding_output = Some(name);
+                    // For the outputs announced before the manager
+                    for (&name, output) in &mut state.outputs {
+                        output.xdg_output =
+                            Some(manager.get_xdg_output(&output.wl_output, qhandle, name));
+                    }
+                    state.xdg_output_manager = Some(manager);
                 }
                 "zwlr_layer_shell_v1" => {
                     state.layer_shell =
@@ -169,46 +741,86 @@ impl Dispatch<WlRegistry, ()> for AppData {
                 "wl_seat" => {
                     state.seat = Some(registry.bind::<WlSeat, _, _>(name, version, qhandle, ()));
                 }
+                "wp_viewporter" => {
+                    state.viewporter =
+                        Some(registry.bind::<WpViewporter, _, _>(name, 1, qhandle, ()));
+                }
+                "wp_presentation" => {
+                    state.presentation =
+                        Some(registry.bind::<WpPresentation, _, _>(name, 1, qhandle, ()));
+                }
+                "wp_fractional_scale_manager_v1" => {
+                    state.fractional_scale_manager = Some(
+                        registry.bind::<WpFractionalScaleManagerV1, _, _>(name, 1, qhandle, ()),
+                    );
+                }
                 "zwlr_virtual_pointer_manager_v1" => {
-                    let manager = registry.bind::<ZwlrVirtualPointerManagerV1, _, _>(
-                        name,
-                        version,
-                        qhandle,
-                        (),
+                    // The pointer itself is created by `take_pointer`
+                    state.virtual_pointer_manager =
+                        Some(registry.bind::<ZwlrVirtualPointerManagerV1, _, _>(
+                            name,
+                            version,
+                            qhandle,
+                            (),
+                        ));
+                }
+                "zwp_virtual_keyboard_manager_v1" => {
+                    // The keyboard itself needs the seat and its keymap
+                    state.virtual_keyboard_manager = Some(
+                        registry.bind::<ZwpVirtualKeyboardManagerV1, _, _>(name, 1, qhandle, ()),
                     );
-                    let pointer = manager.create_virtual_pointer(None, qhandle, ());
-                    state.pointer = Some(pointer);
-                    state.virtual_pointer_manager = Some(manager);
                 }
                 _ => {}
-            },
-            _ => {}
+            }
         }
     }
 }
 
 impl Dispatch<ZwlrVirtualPointerV1, ()> for AppData {
     fn event(
-        state: &mut Self,
-        proxy: &ZwlrVirtualPointerV1,
-        event: <ZwlrVirtualPointerV1 as wayland_client::Proxy>::Event,
-        data: &(),
-        conn: &wayland_client::Connection,
-        qhandle: &wayland_client::QueueHandle<Self>,
+        _state: &mut Self,
+        _proxy: &ZwlrVirtualPointerV1,
+        _event: <ZwlrVirtualPointerV1 as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
-        dbg!(&event);
     }
 }
 impl Dispatch<ZwlrVirtualPointerManagerV1, ()> for AppData {
     fn event(
-        state: &mut Self,
-        proxy: &ZwlrVirtualPointerManagerV1,
-        event: <ZwlrVirtualPointerManagerV1 as wayland_client::Proxy>::Event,
-        data: &(),
-        conn: &wayland_client::Connection,
-        qhandle: &wayland_client::QueueHandle<Self>,
+        _state: &mut Self,
+        _proxy: &ZwlrVirtualPointerManagerV1,
+        _event: <ZwlrVirtualPointerManagerV1 as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwpVirtualKeyboardManagerV1, ()> for AppData {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZwpVirtualKeyboardManagerV1,
+        _event: <ZwpVirtualKeyboardManagerV1 as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
-        dbg!(&event);
+        // Has no events
+    }
+}
+impl Dispatch<ZwpVirtualKeyboardV1, ()> for AppData {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZwpVirtualKeyboardV1,
+        _event: <ZwpVirtualKeyboardV1 as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+        // Has no events
     }
 }
 
@@ -217,137 +829,204 @@ impl Dispatch<WlSeat, ()> for AppData {
         state: &mut Self,
         seat: &WlSeat,
         event: <WlSeat as wayland_client::Proxy>::Event,
-        data: &(),
-        conn: &wayland_client::Connection,
+        _data: &(),
+        _conn: &wayland_client::Connection,
         qh: &wayland_client::QueueHandle<Self>,
     ) {
-        let keyboard = seat.get_keyboard(&qh, ());
+        let keyboard = seat.get_keyboard(qh, ());
         state.keyboard = Some(keyboard);
-        dbg!(&event);
+        if let wl_seat::Event::Capabilities {
+            capabilities: WEnum::Value(capabilities),
+        } = &event
+            && capabilities.contains(wl_seat::Capability::Pointer)
+            && state.wl_pointer.is_none()
+        {
+            state.wl_pointer = Some(seat.get_pointer(qh, ()));
+        }
     }
 }
 
 impl Dispatch<WlKeyboard, ()> for AppData {
     fn event(
         app_state: &mut Self,
-        proxy: &WlKeyboard,
+        _proxy: &WlKeyboard,
         event: <WlKeyboard as wayland_client::Proxy>::Event,
-        data: &(),
-        conn: &wayland_client::Connection,
-        qhandle: &wayland_client::QueueHandle<Self>,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
         match event {
-            wayland_client::protocol::wl_keyboard::Event::Key {
-                serial,
-                time,
-                key,
-                state,
+            wl_keyboard::Event::Keymap {
+                format: WEnum::Value(KeymapFormat::XkbV1),
+                fd,
+                size,
+            } => app_state.keyboard_state.set_keymap(fd, size),
+            wl_keyboard::Event::Modifiers {
+                mods_depressed,
+                mods_latched,
+                mods_locked,
+                group,
+                ..
+            } => {
+                app_state.keyboard_state.update_modifiers(
+                    mods_depressed,
+                    mods_latched,
+                    mods_locked,
+                    group,
+                );
+                let modifiers = app_state.keyboard_state.modifiers();
+                app_state
+                    .keyboard_events
+                    .push(KeyboardEvent::Modifiers(modifiers));
+            }
+            wl_keyboard::Event::RepeatInfo { rate, delay } => {
+                app_state.repeat_info = Some((rate, delay));
+            }
+            wl_keyboard::Event::Leave { .. } => {
+                app_state.keyboard_events.push(KeyboardEvent::Leave);
+            }
+            wl_keyboard::Event::Key {
+                serial, key, state, ..
             } => {
-                if app_state.procesed_keypress_serials.contains(&serial) {
+                if app_state.last_key_serial.replace(serial) == Some(serial) {
                     return;
                 }
-                app_state.procesed_keypress_serials.insert(serial);
-                //if let WEnum::Value(KeyState::Pressed) = state {}
-                //let selector = &app_state.selector;
-                //if selector.depth == 3 {
-                //    app_state.do_click = true;
-                //}
-                if state == WEnum::Value(KeyState::Pressed) {
-                    app_state.selector.as_mut().unwrap().handle_key(key);
-                }
-                if key == 1 {
-                    panic!("escape pressed");
-                }
+                let pressed = state == WEnum::Value(KeyState::Pressed);
+                let key = app_state.keyboard_state.key(key, pressed);
+                app_state.keyboard_events.push(KeyboardEvent::Key(key));
+                // Cleared again by `Session::draw` when the key changed nothing
+                app_state.key_time = app_state.presentation_time();
             }
             _ => {}
         }
     }
 }
 
-impl Dispatch<WlCallback, ()> for AppData {
+impl Dispatch<WlCallback, WlSurface> for AppData {
     fn event(
         state: &mut Self,
-        proxy: &WlCallback,
+        _proxy: &WlCallback,
         event: <WlCallback as wayland_client::Proxy>::Event,
-        data: &(),
-        conn: &wayland_client::Connection,
-        qhandle: &wayland_client::QueueHandle<Self>,
+        wl_surface: &WlSurface,
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
-        dbg!(event);
+        // The only callbacks asked for are frame callbacks
+        if let wl_callback::Event::Done { .. } = event
+            && let Some(surface) = find_surface(&mut state.surfaces, wl_surface)
+        {
+            surface.frame_pending = false;
+        }
     }
 }
+
+impl Dispatch<WpPresentation, ()> for AppData {
+    fn event(
+        state: &mut Self,
+        _proxy: &WpPresentation,
+        event: <WpPresentation as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+        if let wp_presentation::Event::ClockId { clk_id } = event {
+            state.presentation_clock = Some(ClockId::from_raw(clk_id as _));
+        }
+    }
+}
+
+/// Requested for frames that show a key press, with the time of the key
+/// press on the presentation clock.
+impl Dispatch<WpPresentationFeedback, Duration> for AppData {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpPresentationFeedback,
+        event: <WpPresentationFeedback as wayland_client::Proxy>::Event,
+        key_time: &Duration,
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+        match event {
+            wp_presentation_feedback::Event::Presented {
+                tv_sec_hi,
+                tv_sec_lo,
+                tv_nsec,
+                ..
+            } => {
+                let presented = Duration::new((tv_sec_hi as u64) << 32 | tv_sec_lo as u64, tv_nsec);
+                let latency = presented.saturating_sub(*key_time);
+                eprintln!(
+                    "nomouse: {:.1} ms from key press to frame",
+                    latency.as_secs_f64() * 1000.0
+                );
+            }
+            wp_presentation_feedback::Event::Discarded => {
+                eprintln!("nomouse: frame after key press discarded");
+            }
+            _ => {}
+        }
+    }
+}
+
 impl Dispatch<XdgToplevel, ()> for AppData {
     fn event(
         state: &mut Self,
-        proxy: &XdgToplevel,
+        _proxy: &XdgToplevel,
         event: <XdgToplevel as wayland_client::Proxy>::Event,
-        data: &(),
-        conn: &wayland_client::Connection,
+        _data: &(),
+        _conn: &wayland_client::Connection,
         qhandle: &wayland_client::QueueHandle<Self>,
     ) {
-        dbg!(&event);
         match event {
             xdg_toplevel::Event::Configure {
                 width,
                 height,
-                states,
+                states: _,
             } => {
-                let surface = state.surface.as_mut().unwrap();
-                let width = width.max(1);
-                let height = height.max(1);
-                surface.init_buf(width as usize, height as usize);
-                surface.width = width as usize;
-                surface.height = height as usize;
-                let buf = &surface.buf;
-                let shm = state.shm.as_ref().unwrap();
-                let pool = shm.create_pool(buf.fd.as_fd(), buf.len as i32, qhandle, ());
-                let wl_buf =
-                    pool.create_buffer(0, width, height, width * 4, Format::Argb8888, qhandle, ());
-                surface.wl_surface.attach(Some(&wl_buf), 0, 0);
-                surface.wl_surface.damage(0, 0, i32::MAX, i32::MAX);
-                surface.wl_buf = Some(wl_buf);
-                surface.wl_surface.commit();
+                let Some(surface) = state.surfaces.values_mut().next() else {
+                    return;
+                };
+                surface.width = width.max(1) as usize;
+                surface.height = height.max(1) as usize;
+                surface.create_buffer(state.shm.as_ref().unwrap(), qhandle);
             }
             xdg_toplevel::Event::Close => {}
-            xdg_toplevel::Event::ConfigureBounds { width, height } => {}
-            xdg_toplevel::Event::WmCapabilities { capabilities } => {}
+            xdg_toplevel::Event::ConfigureBounds {
+                width: _,
+                height: _,
+            } => {}
+            xdg_toplevel::Event::WmCapabilities { capabilities: _ } => {}
             _ => {}
         }
     }
 }
 impl Dispatch<XdgSurface, ()> for AppData {
     fn event(
-        state: &mut Self,
+        _state: &mut Self,
         proxy: &XdgSurface,
         event: <XdgSurface as wayland_client::Proxy>::Event,
-        data: &(),
-        conn: &wayland_client::Connection,
-        qhandle: &wayland_client::QueueHandle<Self>,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
-        dbg!(&event);
-        match event {
-            wayland_protocols::xdg::shell::client::xdg_surface::Event::Configure { serial } => {
-                proxy.ack_configure(serial);
-            }
-            _ => todo!(),
+        if let wayland_protocols::xdg::shell::client::xdg_surface::Event::Configure { serial } =
+            event
+        {
+            proxy.ack_configure(serial);
         }
     }
 }
 impl Dispatch<XdgWmBase, ()> for AppData {
     fn event(
-        state: &mut Self,
+        _state: &mut Self,
         proxy: &XdgWmBase,
         event: <XdgWmBase as wayland_client::Proxy>::Event,
-        data: &(),
-        conn: &wayland_client::Connection,
-        qhandle: &wayland_client::QueueHandle<Self>,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
-        dbg!(&event);
-        match event {
-            wayland_protocols::xdg::shell::client::xdg_wm_base::Event::Ping { serial } => {
-                proxy.pong(serial)
-            }
-            _ => todo!(),
+        if let wayland_protocols::xdg::shell::client::xdg_wm_base::Event::Ping { serial } = event {
+            proxy.pong(serial)
         }
     }
 }
@@ -356,40 +1035,45 @@ impl Dispatch<WlBuffer, ()> for AppData {
         state: &mut Self,
         proxy: &WlBuffer,
         event: <WlBuffer as wayland_client::Proxy>::Event,
-        data: &(),
-        conn: &wayland_client::Connection,
-        qhandle: &wayland_client::QueueHandle<Self>,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
-        dbg!(event);
+        if let wl_buffer::Event::Release = event {
+            // Surfaces that are gone destroyed their buffers already
+            state
+                .surfaces
+                .values_mut()
+                .filter_map(|surface| surface.buffers.as_mut())
+                .any(|buffers| buffers.release(proxy));
+        }
     }
 }
 
 impl Dispatch<WlShm, ()> for AppData {
     fn event(
         state: &mut Self,
-        proxy: &WlShm,
+        _proxy: &WlShm,
         event: <WlShm as wayland_client::Proxy>::Event,
-        data: &(),
-        conn: &wayland_client::Connection,
-        qhandle: &wayland_client::QueueHandle<Self>,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
-        match event {
-            wl_shm::Event::Format { format } => state.formats.push(format),
-            _ => todo!(),
-        };
+        if let wl_shm::Event::Format { format } = event {
+            state.formats.push(format);
+        }
     }
 }
 
 impl Dispatch<WlCompositor, ()> for AppData {
     fn event(
-        state: &mut Self,
-        compositor: &WlCompositor,
-        event: <WlCompositor as wayland_client::Proxy>::Event,
-        data: &(),
-        conn: &wayland_client::Connection,
-        qhandle: &wayland_client::QueueHandle<Self>,
+        _state: &mut Self,
+        _compositor: &WlCompositor,
+        _event: <WlCompositor as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
-        dbg!(event);
     }
 }
 
@@ -398,41 +1082,91 @@ impl Dispatch<WlSurface, ()> for AppData {
         state: &mut Self,
         proxy: &WlSurface,
         event: <WlSurface as wayland_client::Proxy>::Event,
-        data: &(),
-        conn: &wayland_client::Connection,
+        _data: &(),
+        _conn: &wayland_client::Connection,
         qhandle: &wayland_client::QueueHandle<Self>,
     ) {
-        dbg!(event);
+        let wl_surface::Event::Enter { output } = event else {
+            return;
+        };
+        let Some(&name) = output.data::<u32>() else {
+            return;
+        };
+        let Some(surface) = find_surface(&mut state.surfaces, proxy) else {
+            return;
+        };
+        if surface.output != UNKNOWN_OUTPUT {
+            return;
+        }
+        // The compositor picked the output of this surface, file it under it
+        let mut surface = state.surfaces.remove(&UNKNOWN_OUTPUT).unwrap();
+        surface.output = name;
+        if let Some(output) = state.outputs.get_mut(&name) {
+            output.logical_width = Some(surface.width as i32);
+            output.logical_height = Some(surface.height as i32);
+            let scale = output.scale.unwrap_or(1).max(1) as f64;
+            // Without fractional scaling the scale comes from the output
+            if surface.fractional_scale.is_none() && scale != surface.scale {
+                surface.scale = scale;
+                surface.create_buffer(state.shm.as_ref().unwrap(), qhandle);
+            }
+        }
+        state.surfaces.insert(name, surface);
+        state.layout_changed = true;
     }
 }
 
-impl Dispatch<WlShmPool, ()> for AppData {
+impl Dispatch<WlPointer, ()> for AppData {
     fn event(
         state: &mut Self,
-        proxy: &WlShmPool,
-        event: <WlShmPool as wayland_client::Proxy>::Event,
-        data: &(),
-        conn: &wayland_client::Connection,
-        qhandle: &wayland_client::QueueHandle<Self>,
+        _proxy: &WlPointer,
+        event: <WlPointer as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
-        dbg!(event);
+        if let wl_pointer::Event::Enter { surface, .. } = event {
+            state.entered_surface = Some(surface);
+        }
     }
 }
 
-impl Dispatch<WlOutput, ()> for AppData {
+impl Dispatch<WlRegion, ()> for AppData {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WlRegion,
+        _event: <WlRegion as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WlShmPool, ()> for AppData {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WlShmPool,
+        _event: <WlShmPool as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WlOutput, u32> for AppData {
     fn event(
         state: &mut Self,
-        proxy: &WlOutput,
+        _proxy: &WlOutput,
         event: <WlOutput as wayland_client::Proxy>::Event,
-        data: &(),
-        conn: &wayland_client::Connection,
+        name: &u32,
+        _conn: &wayland_client::Connection,
         qhandle: &wayland_client::QueueHandle<Self>,
     ) {
-        dbg!(&event);
-        let output = state
-            .outputs
-            .get_mut(&state.adding_output.unwrap())
-            .unwrap();
+        let Some(output) = state.outputs.get_mut(name) else {
+            return;
+        };
         match event {
             wayland_client::protocol::wl_output::Event::Geometry {
                 x,
@@ -459,10 +1193,15 @@ impl Dispatch<WlOutput, ()> for AppData {
                 height,
                 refresh,
             } => {
-                output.flags = Some(flags);
-                output.width = Some(width);
-                output.height = Some(height);
-                output.refresh = Some(refresh);
+                // Every supported mode is advertised, only the current one is relevant
+                if let WEnum::Value(mode) = flags
+                    && mode.contains(Mode::Current)
+                {
+                    output.flags = Some(flags);
+                    output.width = Some(width);
+                    output.height = Some(height);
+                    output.refresh = Some(refresh);
+                }
             }
             wayland_client::protocol::wl_output::Event::Scale { factor } => {
                 output.scale = Some(factor);
@@ -474,68 +1213,117 @@ impl Dispatch<WlOutput, ()> for AppData {
                 output.description = Some(description);
             }
             wayland_client::protocol::wl_output::Event::Done => {
-                state.adding_output = None;
+                let was_done = output.done;
+                output.done = true;
+                // Plugged in while the overlay is shown, show it there too
+                if !was_done
+                    && state.target == OutputTarget::All
+                    && !state.surfaces.is_empty()
+                    && !state.surfaces.contains_key(name)
+                {
+                    state.create_surface(Some(*name), qhandle);
+                    state.layout_changed = true;
+                }
             }
-            _ => todo!(),
+            _ => {}
         }
     }
 }
 
-impl Dispatch<ZwlrLayerShellV1, ()> for AppData {
+impl Dispatch<ZxdgOutputManagerV1, ()> for AppData {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZxdgOutputManagerV1,
+        _event: <ZxdgOutputManagerV1 as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+        // Has no events
+    }
+}
+
+impl Dispatch<ZxdgOutputV1, u32> for AppData {
     fn event(
         state: &mut Self,
-        proxy: &ZwlrLayerShellV1,
-        event: <ZwlrLayerShellV1 as wayland_client::Proxy>::Event,
-        data: &(),
-        conn: &wayland_client::Connection,
-        qhandle: &wayland_client::QueueHandle<Self>,
+        _proxy: &ZxdgOutputV1,
+        event: <ZxdgOutputV1 as wayland_client::Proxy>::Event,
+        name: &u32,
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+        let Some(output) = state.outputs.get_mut(name) else {
+            return;
+        };
+        match event {
+            zxdg_output_v1::Event::LogicalPosition { x, y } => {
+                output.x = Some(x);
+                output.y = Some(y);
+            }
+            zxdg_output_v1::Event::LogicalSize { width, height } => {
+                output.logical_width = Some(width);
+                output.logical_height = Some(height);
+            }
+            // Only sent here by compositors with a wl_output older than 4
+            zxdg_output_v1::Event::Name { name } => {
+                output.name.get_or_insert(name);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZwlrLayerShellV1, ()> for AppData {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZwlrLayerShellV1,
+        _event: <ZwlrLayerShellV1 as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
-        dbg!(&event);
     }
 }
 
-impl Dispatch<ZwlrLayerSurfaceV1, ()> for AppData {
+impl Dispatch<ZwlrLayerSurfaceV1, WlSurface> for AppData {
     fn event(
         state: &mut Self,
         proxy: &ZwlrLayerSurfaceV1,
         event: <ZwlrLayerSurfaceV1 as wayland_client::Proxy>::Event,
-        data: &(),
-        conn: &wayland_client::Connection,
+        wl_surface: &WlSurface,
+        _conn: &wayland_client::Connection,
         qhandle: &wayland_client::QueueHandle<Self>,
     ) {
-        dbg!(&event);
         match event {
             zwlr_layer_surface_v1::Event::Configure {
                 serial,
                 width,
                 height,
             } => {
-                let surface = state.surface.as_mut().unwrap();
-                let width = width.max(1);
-                let height = height.max(1);
-                surface.init_buf(width as usize, height as usize);
-                //surface.width = width as usize;
-                surface.width = 1920;
-                surface.height = height as usize;
-                let buf = &surface.buf;
-                let shm = state.shm.as_ref().unwrap();
-                let pool = shm.create_pool(buf.fd.as_fd(), buf.len as i32, qhandle, ());
-                let wl_buf = pool.create_buffer(
-                    0,
-                    width as i32,
-                    height as i32,
-                    (width * 4) as i32,
-                    Format::Argb8888,
-                    qhandle,
-                    (),
-                );
-                //surface.wl_surface.attach(Some(&wl_buf), 0, 0);
-                //surface.wl_surface.damage(0, 0, i32::MAX, i32::MAX);
-                surface.wl_buf = Some(wl_buf);
-                state.layer_surface.as_ref().unwrap().ack_configure(serial);
+                let Some(surface) = find_surface(&mut state.surfaces, wl_surface) else {
+                    return;
+                };
+                let size = (width.max(1) as usize, height.max(1) as usize);
+                if size != (surface.width, surface.height) {
+                    (surface.width, surface.height) = size;
+                    state.layout_changed = true;
+                }
+                surface.create_buffer(state.shm.as_ref().unwrap(), qhandle);
+                // Anchored to all edges, so this is the logical size of the output
+                if let Some(output) = state.outputs.get_mut(&surface.output) {
+                    output.logical_width = Some(surface.width as i32);
+                    output.logical_height = Some(surface.height as i32);
+                }
+                proxy.ack_configure(serial);
+            }
+            // The output went away or the compositor wants the overlay gone
+            zwlr_layer_surface_v1::Event::Closed => {
+                if let Some(surface) = find_surface(&mut state.surfaces, wl_surface) {
+                    let output = surface.output;
+                    state.remove_surface(output);
+                }
             }
-            zwlr_layer_surface_v1::Event::Closed => todo!(),
-            _ => todo!(),
+            _ => {}
         }
         //let surface = state.surface.as_mut().unwrap();
         //let width = width.max(1);
@@ -553,3 +1341,259 @@ impl Dispatch<ZwlrLayerSurfaceV1, ()> for AppData {
         //surface.wl_surface.commit();
     }
 }
+
+impl Dispatch<WpViewporter, ()> for AppData {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpViewporter,
+        _event: <WpViewporter as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+        // Has no events
+    }
+}
+
+impl Dispatch<WpViewport, ()> for AppData {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpViewport,
+        _event: <WpViewport as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+        // Has no events
+    }
+}
+
+impl Dispatch<WpFractionalScaleManagerV1, ()> for AppData {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpFractionalScaleManagerV1,
+        _event: <WpFractionalScaleManagerV1 as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+        // Has no events
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, WlSurface> for AppData {
+    fn event(
+        state: &mut Self,
+        _proxy: &WpFractionalScaleV1,
+        event: <WpFractionalScaleV1 as wayland_client::Proxy>::Event,
+        wl_surface: &WlSurface,
+        _conn: &wayland_client::Connection,
+        qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+        let wp_fractional_scale_v1::Event::PreferredScale { scale } = event else {
+            return;
+        };
+        let Some(surface) = find_surface(&mut state.surfaces, wl_surface) else {
+            return;
+        };
+        // Sent as a multiple of 1/120
+        let scale = scale as f64 / 120.0;
+        if scale == surface.scale {
+            return;
+        }
+        surface.scale = scale;
+        if surface.is_configured() {
+            surface.create_buffer(state.shm.as_ref().unwrap(), qhandle);
+            state.layout_changed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        os::unix::net::UnixStream,
+        sync::{
+            Arc,
+            atomic::{AtomicBool, Ordering},
+        },
+        thread::{self, JoinHandle},
+        time::Duration,
+    };
+
+    use wayland_protocols::xdg::xdg_output::zv1::server::{
+        zxdg_output_manager_v1::{self, ZxdgOutputManagerV1},
+        zxdg_output_v1::ZxdgOutputV1,
+    };
+    use wayland_server::{
+        Client, DataInit, Dispatch, Display, DisplayHandle, GlobalDispatch, New, Resource,
+        backend::{ClientData, ClientId, DisconnectReason},
+        protocol::{
+            wl_compositor::WlCompositor,
+            wl_output::{self, WlOutput},
+            wl_shm::WlShm,
+        },
+    };
+
+    use super::AppData;
+
+    /// A compositor without `zwlr_layer_shell_v1`, with one output at a
+    /// scale of 1.5. Integer scales round that up to 2.
+    struct Compositor;
+
+    struct NoData;
+
+    impl ClientData for NoData {
+        fn initialized(&self, _client_id: ClientId) {}
+        fn disconnected(&self, _client_id: ClientId, _reason: DisconnectReason) {}
+    }
+
+    /// Globals that need nothing sent when they are bound.
+    macro_rules! globals {
+        ($($interface:ty),*) => {$(
+            impl GlobalDispatch<$interface, ()> for Compositor {
+                fn bind(
+                    _state: &mut Self,
+                    _handle: &DisplayHandle,
+                    _client: &Client,
+                    resource: New<$interface>,
+                    _global_data: &(),
+                    data_init: &mut DataInit<'_, Self>,
+                ) {
+                    data_init.init(resource, ());
+                }
+            }
+        )*};
+    }
+
+    /// Objects whose requests don't matter to the tests.
+    macro_rules! ignore_requests {
+        ($($interface:ty),*) => {$(
+            impl Dispatch<$interface, ()> for Compositor {
+                fn request(
+                    _state: &mut Self,
+                    _client: &Client,
+                    _resource: &$interface,
+                    _request: <$interface as Resource>::Request,
+                    _data: &(),
+                    _dhandle: &DisplayHandle,
+                    _data_init: &mut DataInit<'_, Self>,
+                ) {
+                }
+            }
+        )*};
+    }
+
+    globals!(WlCompositor, WlShm, ZxdgOutputManagerV1);
+    ignore_requests!(WlCompositor, WlShm, WlOutput, ZxdgOutputV1);
+
+    impl GlobalDispatch<WlOutput, ()> for Compositor {
+        fn bind(
+            _state: &mut Self,
+            _handle: &DisplayHandle,
+            _client: &Client,
+            resource: New<WlOutput>,
+            _global_data: &(),
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            let output = data_init.init(resource, ());
+            output.geometry(
+                -1920,
+                0,
+                300,
+                200,
+                wl_output::Subpixel::Unknown,
+                "make".into(),
+                "model".into(),
+                wl_output::Transform::Normal,
+            );
+            output.mode(wl_output::Mode::Current, 2880, 1800, 60000);
+            output.scale(2);
+            output.done();
+        }
+    }
+
+    impl Dispatch<ZxdgOutputManagerV1, ()> for Compositor {
+        fn request(
+            _state: &mut Self,
+            _client: &Client,
+            _resource: &ZxdgOutputManagerV1,
+            request: zxdg_output_manager_v1::Request,
+            _data: &(),
+            _dhandle: &DisplayHandle,
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            if let zxdg_output_manager_v1::Request::GetXdgOutput { id, .. } = request {
+                let xdg_output = data_init.init(id, ());
+                xdg_output.logical_position(-1920, 0);
+                xdg_output.logical_size(1920, 1200);
+            }
+        }
+    }
+
+    /// Runs the compositor on a thread until `stop` is set, with the
+    /// globals `create_globals` creates. Returns the socket to connect to.
+    fn serve(
+        create_globals: impl FnOnce(&DisplayHandle) + Send + 'static,
+        stop: Arc<AtomicBool>,
+    ) -> (UnixStream, JoinHandle<()>) {
+        let (server_socket, client_socket) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            let mut display = Display::<Compositor>::new().unwrap();
+            let mut handle = display.handle();
+            create_globals(&handle);
+            handle
+                .insert_client(server_socket, Arc::new(NoData))
+                .unwrap();
+            while !stop.load(Ordering::Relaxed) {
+                display.dispatch_clients(&mut Compositor).unwrap();
+                display.flush_clients().unwrap();
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        (client_socket, server)
+    }
+
+    #[test]
+    fn names_missing_layer_shell() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (socket, server) = serve(
+            |handle| {
+                handle.create_global::<Compositor, WlCompositor, ()>(2, ());
+                handle.create_global::<Compositor, WlShm, ()>(1, ());
+                handle.create_global::<Compositor, WlOutput, ()>(2, ());
+            },
+            stop.clone(),
+        );
+        let conn = wayland_client::Connection::from_socket(socket).unwrap();
+        let err = AppData::connect_to(conn).unwrap_err();
+        stop.store(true, Ordering::Relaxed);
+        server.join().unwrap();
+        assert_eq!(err, "the compositor doesn't support zwlr_layer_shell_v1");
+    }
+
+    #[test]
+    fn logical_size_with_fractional_scale() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (socket, server) = serve(
+            |handle| {
+                handle.create_global::<Compositor, WlOutput, ()>(2, ());
+                // Announced after the output, which has to be picked up too
+                handle.create_global::<Compositor, ZxdgOutputManagerV1, ()>(3, ());
+            },
+            stop.clone(),
+        );
+        let conn = wayland_client::Connection::from_socket(socket).unwrap();
+        let mut event_queue = conn.new_event_queue();
+        conn.display().get_registry(&event_queue.handle(), ());
+        let mut app = AppData::default();
+        // Fails without the other globals, after getting to the outputs
+        assert!(app.init_that_shit(&mut event_queue).is_err());
+        stop.store(true, Ordering::Relaxed);
+        server.join().unwrap();
+        let output = app.outputs.values().next().unwrap();
+        // Not 2880x1800 divided by 2
+        assert_eq!(output.logical_size(), (1920, 1200));
+        assert_eq!(app.layout_extents(), (-1920, 0, 1920, 1200));
+    }
+}
@@ -0,0 +1,102 @@
+// What a selection needs from the display server, so `Session` runs the same
+// on Wayland and on X11.
+
+use crate::{
+    config::Config,
+    keyboard_utils::{KeyboardEvent, Modifiers},
+    output_utils::OutputTarget,
+    pointer::{BackendKind, Extents, PointerBackend},
+    position_selector::{FinalSelector, SelectorState},
+};
+
+/// An output as the selection sees it, see `Backend::outputs`.
+#[derive(Debug, Clone)]
+pub struct OutputInfo {
+    /// The output of the grid on it, the registry name on Wayland
+    pub id: u32,
+    pub name: Option<String>,
+    /// Position and size in the global space
+    pub rect: (i32, i32, i32, i32),
+    /// Whether the overlay is shown on it
+    pub shown: bool,
+}
+
+impl OutputInfo {
+    /// Whether the global position `(x, y)` is on the output.
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        let (left, top, width, height) = self.rect;
+        (left..left + width).contains(&x) && (top..top + height).contains(&y)
+    }
+}
+
+/// Draws the selection into the buffer of an output, given the output, the
+/// buffer in ARGB8888, its width and height and its scale.
+pub type Render<'a> = dyn FnMut(u32, &mut [u8], usize, usize, f64) + 'a;
+
+/// Shows the overlay and fakes input on one kind of display server.
+pub trait Backend {
+    /// Connects to the `kind` of pointer backend, or hands out the one kept
+    /// by `keep_pointer`.
+    fn take_pointer(&mut self, kind: BackendKind) -> Result<Box<dyn PointerBackend>, String>;
+
+    /// Keeps `pointer` for the next `take_pointer`.
+    fn keep_pointer(&mut self, kind: BackendKind, pointer: Box<dyn PointerBackend>);
+
+    /// Shows the overlay on the outputs `target` picks and takes the
+    /// keyboard. Input to the pointer passes through it.
+    fn show(&mut self, target: &OutputTarget) -> Result<(), String>;
+
+    /// Takes the overlay down and gives the keyboard back, waiting until the
+    /// display server has done so.
+    fn hide(&mut self);
+
+    /// Whether the overlay is still shown, every output may have gone away.
+    fn is_shown(&self) -> bool;
+
+    /// The keyboard events that arrived since the last call.
+    fn take_keyboard_events(&mut self) -> Vec<KeyboardEvent>;
+
+    /// Whether outputs came or went or changed size since the last call,
+    /// which the grid has to be laid out again for.
+    fn take_layout_changed(&mut self) -> bool;
+
+    /// Builds a grid for every output the overlay is shown on. With more
+    /// than one the first key picks the output, ordered left to right.
+    fn create_selector(&self, config: &Config) -> Result<SelectorState, String>;
+
+    /// The center of the selection in the global space, or `None` when its
+    /// output went away.
+    fn global_position(&self, selector: &FinalSelector) -> Option<(i32, i32)>;
+
+    /// The extents of all outputs, which the pointer is moved within.
+    fn layout_extents(&self) -> Extents;
+
+    /// Every output, whether the overlay is shown on it or not.
+    fn outputs(&self) -> Vec<OutputInfo>;
+
+    /// Key repeat rate and delay, when they are known.
+    fn repeat_info(&self) -> Option<(i32, i32)>;
+
+    /// Holds down `held` for the clicks that follow.
+    fn press_held_modifiers(&mut self, held: Modifiers);
+
+    /// Lets go of the modifiers held down by `press_held_modifiers`.
+    fn release_held_modifiers(&mut self, held: Modifiers);
+
+    /// Types `text` into whatever has the keyboard focus.
+    fn type_text(&mut self, text: &str) -> Result<(), String>;
+
+    /// Has every overlay drawn again with the next `draw`, after something
+    /// it shows changed.
+    fn request_redraw(&mut self);
+
+    /// Draws the overlays that need it with `render`. Without one there is
+    /// nothing to show, the overlay is only kept for the keyboard.
+    fn draw(&mut self, render: Option<&mut Render>);
+
+    /// Sends the requests made so far, like the events of the pointer.
+    fn flush(&mut self);
+
+    /// Waits until the display server has handled the requests made so far.
+    fn sync(&mut self);
+}
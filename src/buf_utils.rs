@@ -4,7 +4,21 @@ use std::{
     os::fd::{AsFd, OwnedFd},
     ptr::NonNull,
 };
-use wayland_client::protocol::{wl_buffer::WlBuffer, wl_surface::WlSurface};
+use wayland_client::{
+    QueueHandle,
+    protocol::{
+        wl_buffer::WlBuffer,
+        wl_compositor::WlCompositor,
+        wl_shm::{Format, WlShm},
+        wl_shm_pool::WlShmPool,
+        wl_surface::WlSurface,
+    },
+};
+use wayland_protocols::wp::{
+    fractional_scale::v1::client::wp_fractional_scale_v1::WpFractionalScaleV1,
+    viewporter::client::wp_viewport::WpViewport,
+};
+use wayland_protocols_wlr::layer_shell::v1::client::zwlr_layer_surface_v1::ZwlrLayerSurfaceV1;
 
 use nix::{
     fcntl::OFlag,
@@ -15,18 +29,233 @@ use nix::{
     unistd::ftruncate,
 };
 
+use crate::app::AppData;
+
 #[derive(Debug)]
 pub struct Surface {
+    pub output: u32,
+    /// Logical size, as configured by the compositor
     pub width: usize,
     pub height: usize,
+    /// Buffer pixels per logical pixel
+    pub scale: f64,
     pub wl_surface: WlSurface,
-    pub buf: MMappedBuf,
-    pub wl_buf: Option<WlBuffer>,
+    pub layer_surface: ZwlrLayerSurfaceV1,
+    /// Only there when the compositor supports viewporter, buffers are
+    /// scaled with `set_buffer_scale` otherwise
+    pub viewport: Option<WpViewport>,
+    pub fractional_scale: Option<WpFractionalScaleV1>,
+    /// Created once the compositor configured the size
+    pub buffers: Option<BufferPool>,
+    /// What the overlay shows changed since it was last drawn
+    pub dirty: bool,
+    /// A frame was committed and its `wl_surface.frame` callback hasn't
+    /// fired yet, drawing waits for it
+    pub frame_pending: bool,
 }
 
 impl Surface {
-    pub fn init_buf(&mut self, width: usize, height: usize) {
-        self.buf = allocate_shm_buffer(width * height * 4 * 2);
+    pub fn is_configured(&self) -> bool {
+        self.buffers.is_some()
+    }
+
+    pub fn buffer_width(&self) -> usize {
+        (self.width as f64 * self.scale).round() as usize
+    }
+
+    pub fn buffer_height(&self) -> usize {
+        (self.height as f64 * self.scale).round() as usize
+    }
+
+    /// Sizes the buffers to the physical size of the surface, for after its
+    /// size or scale changed.
+    pub fn create_buffer(&mut self, shm: &WlShm, qh: &QueueHandle<AppData>) {
+        let size = (self.buffer_width(), self.buffer_height());
+        match &mut self.buffers {
+            Some(buffers) => buffers.resize(size),
+            None => self.buffers = Some(BufferPool::new(shm, size, qh)),
+        }
+        match &self.viewport {
+            Some(viewport) => viewport.set_destination(self.width as i32, self.height as i32),
+            None => self.wl_surface.set_buffer_scale(self.scale as i32),
+        }
+        // The new buffer is blank
+        self.dirty = true;
+    }
+
+    /// Lets pointer events through to the windows below, so a drag can be
+    /// started while the overlay is still shown.
+    pub fn pass_pointer_through(&self, compositor: &WlCompositor, qh: &QueueHandle<AppData>) {
+        let region = compositor.create_region(qh, ());
+        self.wl_surface.set_input_region(Some(&region));
+        region.destroy();
+    }
+
+    pub fn destroy(&self) {
+        if let Some(fractional_scale) = &self.fractional_scale {
+            fractional_scale.destroy();
+        }
+        if let Some(viewport) = &self.viewport {
+            viewport.destroy();
+        }
+        self.layer_surface.destroy();
+        self.wl_surface.destroy();
+    }
+}
+
+/// How many buffers a surface draws into in turns, one can be drawn while
+/// the compositor still reads the other.
+const BUFFERS_PER_SURFACE: usize = 2;
+
+/// A buffer in a `BufferPool`.
+#[derive(Debug)]
+struct Buffer {
+    wl_buffer: WlBuffer,
+    offset: usize,
+    size: (usize, usize),
+    /// Committed and not released by the compositor yet, it may be
+    /// reading from it
+    busy: bool,
+}
+
+impl Buffer {
+    fn len(&self) -> usize {
+        self.size.0 * self.size.1 * 4
+    }
+
+    fn overlaps(&self, offset: usize, len: usize) -> bool {
+        self.offset < offset + len && offset < self.offset + self.len()
+    }
+}
+
+/// The buffers of a surface, all in one shm pool that grows when the
+/// surface does. Buffers of an earlier size are kept until the compositor
+/// releases them, and the memory they use isn't drawn into before that.
+#[derive(Debug)]
+pub struct BufferPool {
+    mem: MMappedBuf,
+    pool: WlShmPool,
+    buffers: Vec<Buffer>,
+    /// In buffer pixels
+    size: (usize, usize),
+}
+
+impl BufferPool {
+    pub fn new(shm: &WlShm, size: (usize, usize), qh: &QueueHandle<AppData>) -> Self {
+        let mem = allocate_shm_buffer(Self::len_for(size));
+        let pool = shm.create_pool(mem.fd.as_fd(), mem.len as i32, qh, ());
+        Self {
+            mem,
+            pool,
+            buffers: Vec::new(),
+            size,
+        }
+    }
+
+    fn len_for((width, height): (usize, usize)) -> usize {
+        (width * height * 4 * BUFFERS_PER_SURFACE).max(1)
+    }
+
+    /// Buffers handed out from now on have `size`, the pool grows to fit
+    /// them. Buffers of the old size are destroyed once they are released.
+    pub fn resize(&mut self, size: (usize, usize)) {
+        if size == self.size {
+            return;
+        }
+        self.size = size;
+        self.buffers.retain(|buffer| {
+            if buffer.busy {
+                return true;
+            }
+            buffer.wl_buffer.destroy();
+            false
+        });
+        let len = Self::len_for(size);
+        if len > self.mem.len {
+            // Pools can only grow, which leaves the busy buffers in place
+            self.mem.grow(len);
+            self.pool.resize(len as i32);
+        }
+    }
+
+    /// A buffer of the current size that the compositor isn't reading
+    /// from, to draw the next frame into and commit. `None` while all of
+    /// them are busy, until one is released.
+    pub fn acquire(&mut self, qh: &QueueHandle<AppData>) -> Option<(WlBuffer, &mut [u8])> {
+        let (width, height) = self.size;
+        let len = width * height * 4;
+        let index = (0..BUFFERS_PER_SURFACE).find_map(|slot| {
+            let offset = slot * len;
+            let existing = self
+                .buffers
+                .iter()
+                .position(|buffer| buffer.offset == offset && buffer.size == self.size);
+            match existing {
+                Some(index) => (!self.buffers[index].busy).then_some(index),
+                // Left over buffers of another size may still be in the way
+                None if self
+                    .buffers
+                    .iter()
+                    .any(|buffer| buffer.busy && buffer.overlaps(offset, len)) =>
+                {
+                    None
+                }
+                None => {
+                    let wl_buffer = self.pool.create_buffer(
+                        offset as i32,
+                        width as i32,
+                        height as i32,
+                        (width * 4) as i32,
+                        Format::Argb8888,
+                        qh,
+                        (),
+                    );
+                    self.buffers.push(Buffer {
+                        wl_buffer,
+                        offset,
+                        size: self.size,
+                        busy: false,
+                    });
+                    Some(self.buffers.len() - 1)
+                }
+            }
+        })?;
+        let buffer = &mut self.buffers[index];
+        buffer.busy = true;
+        let range = buffer.offset..buffer.offset + len;
+        Some((
+            buffer.wl_buffer.clone(),
+            &mut self.mem.as_mut_slice()[range],
+        ))
+    }
+
+    /// Marks `wl_buffer` as free again, returns whether it is from this
+    /// pool.
+    pub fn release(&mut self, wl_buffer: &WlBuffer) -> bool {
+        let Some(index) = self
+            .buffers
+            .iter()
+            .position(|buffer| buffer.wl_buffer == *wl_buffer)
+        else {
+            return false;
+        };
+        if self.buffers[index].size == self.size {
+            self.buffers[index].busy = false;
+        } else {
+            self.buffers.remove(index).wl_buffer.destroy();
+        }
+        true
+    }
+}
+
+impl Drop for BufferPool {
+    fn drop(&mut self) {
+        for buffer in &self.buffers {
+            buffer.wl_buffer.destroy();
+        }
+        // The memory stays around for the compositor until the buffers
+        // are gone on its side too
+        self.pool.destroy();
     }
 }
 
@@ -34,17 +263,22 @@ impl Surface {
 pub struct MMappedBuf {
     pub fd: OwnedFd,
     ptr: NonNull<c_void>,
-    shm_name: String,
     pub len: usize,
 }
 
 impl MMappedBuf {
-    pub fn as_slice<'a>(&'a self) -> &'a [u8] {
-        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr() as *mut u8, self.len) }
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr() as *mut u8, self.len) }
     }
 
-    pub fn as_mut_slice<'a>(&'a mut self) -> &'a mut [u8] {
-        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr() as *mut u8, self.len) }
+    /// Grows the memory to `len` bytes, keeping what is in it.
+    pub fn grow(&mut self, len: usize) {
+        ftruncate(&self.fd, len as i64).unwrap();
+        unsafe {
+            let _ = nix::sys::mman::munmap(self.ptr, self.len);
+        };
+        self.ptr = map(&self.fd, len);
+        self.len = len;
     }
 }
 
@@ -53,20 +287,11 @@ impl Drop for MMappedBuf {
         unsafe {
             let _ = nix::sys::mman::munmap(self.ptr, self.len);
         };
-        shm_unlink(self.shm_name.as_str().into()).unwrap();
     }
 }
 
-pub fn allocate_shm_buffer<'a>(len: usize) -> MMappedBuf {
-    let name = format!("/nomouse-buf-{}", nanoid!());
-    let fd = shm_open(
-        name.as_str().into(),
-        OFlag::O_CREAT | OFlag::O_RDWR | OFlag::O_EXCL,
-        Mode::from_bits(0600).unwrap(),
-    )
-    .unwrap();
-    ftruncate(&fd, len as i64).unwrap();
-    let ptr = unsafe {
+fn map(fd: &OwnedFd, len: usize) -> NonNull<c_void> {
+    unsafe {
         nix::sys::mman::mmap(
             None,
             len.try_into().unwrap(),
@@ -76,11 +301,21 @@ pub fn allocate_shm_buffer<'a>(len: usize) -> MMappedBuf {
             0,
         )
         .unwrap()
-    };
-    MMappedBuf {
-        shm_name: name,
-        fd,
-        ptr,
-        len,
     }
 }
+
+pub fn allocate_shm_buffer(len: usize) -> MMappedBuf {
+    let name = format!("/nomouse-buf-{}", nanoid!());
+    let fd = shm_open(
+        name.as_str(),
+        OFlag::O_CREAT | OFlag::O_RDWR | OFlag::O_EXCL,
+        Mode::S_IRUSR | Mode::S_IWUSR,
+    )
+    .unwrap();
+    // Only needed to get the fd, the memory goes away with the last fd or
+    // mapping of it, in here or in the compositor
+    shm_unlink(name.as_str()).unwrap();
+    ftruncate(&fd, len as i64).unwrap();
+    let ptr = map(&fd, len);
+    MMappedBuf { fd, ptr, len }
+}
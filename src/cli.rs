@@ -0,0 +1,376 @@
+use std::path::PathBuf;
+
+use crate::{actions::Action, output_utils::OutputTarget};
+
+pub const USAGE: &str = "\
+Usage: nomouse [OPTIONS]
+       nomouse --repeat-last
+       nomouse daemon
+       nomouse msg show [OPTIONS]
+       nomouse msg cancel
+       nomouse preview FILE [--size WIDTHxHEIGHT] [--scale SCALE] [--keys KEYS]
+
+Commands:
+  daemon                 Stay connected to the compositor in the background,
+                         so the overlay shows up instantly when asked for
+  msg show [OPTIONS]     Make the running daemon show the overlay, taking the
+                         same options as nomouse itself. Prints and exits
+                         like nomouse would once the selection is done
+  msg cancel             Cancel the overlay the daemon is showing
+  preview FILE           Write the overlay to a PNG file instead of showing
+                         it, to try out a config. --size is the logical
+                         size of the output to pretend (1920x1080 by
+                         default), --scale its scale (1 by default) and
+                         --keys the labels to type before rendering
+
+Options:
+  -a, --action <ACTION>  What to do at the selected position: left, right,
+                         middle, double, triple or none. Defaults to
+                         click.action from the config. Holding shift, ctrl
+                         or alt while typing the last label overrides it
+                         with click.shift_action, click.ctrl_action or
+                         click.alt_action.
+  -d, --drag             Pick two positions and drag from the first one to
+                         the second one with the left button held
+  -s, --scroll           Move the pointer to the selected position, then
+                         scroll with the scroll.up/down/left/right keys
+                         from the config (k, j, h and l by default) until
+                         escape is pressed
+  -n, --normal           Move the pointer to the selected position, then
+                         move it further with the normal.up/down/left/right
+                         keys (k, j, h and l by default) while they are
+                         held, faster the longer they are, or slower while
+                         shift is held. Space or enter performs the action
+  -t, --type <TEXT>      Type TEXT after clicking, e.g. into the search box
+                         that was clicked. \\n types enter, \\t tab and \\\\ a
+                         backslash
+  -p, --print            Print the selected position to stdout instead of
+                         clicking, in global compositor coordinates
+  -r, --region           Select two positions and print the rectangle
+                         between them to stdout
+  -f, --format <FORMAT>  Format for --print and --region. %x and %y are the
+                         position or the top left corner of the region, %w
+                         and %h the size of the region, %o the output name
+                         and %% a literal %. Defaults to \"%x,%y\" for
+                         --print and \"%x,%y %wx%h\" (like slurp) for --region
+  -o, --output <OUTPUT>  Where to show the overlay: focused, all, pointer or
+                         the name or description of an output. Defaults to
+                         output from the config, which defaults to focused
+      --repeat-last      Click at the position of the last click again, with
+                         the same action, without showing the overlay
+  -h, --help             Print this help
+
+Keys:
+  escape                 Cancel without clicking
+  backspace              Undo the last typed label
+  shift, ctrl, alt, super
+                         Tapped on their own: toggle holding them during
+                         the click
+  0-9                    Before the first label: click that many times
+  M then a character     Save the center of the current cell as a mark
+  ' then a character     Select that mark instead of typing labels
+
+Exit status:
+  0  The selected action was performed
+  1  Error, e.g. an invalid config file, an unknown output or no daemon
+     running
+  2  Invalid command line arguments
+  3  Cancelled with escape
+";
+
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_CANCELLED: i32 = 3;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Click,
+    Drag,
+    Scroll,
+    Normal,
+    Print,
+    Region,
+}
+
+/// What to do, picked by the first argument.
+#[derive(Debug)]
+pub enum Command {
+    /// Show the overlay right away
+    Run(Args),
+    Daemon,
+    /// Send the rest of the arguments to the daemon
+    Msg(Vec<String>),
+    Preview(PreviewArgs),
+}
+
+impl Command {
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.peekable();
+        match args.peek().map(String::as_str) {
+            Some("daemon") => {
+                args.next();
+                match args.next() {
+                    Some(arg) => Err(format!("unknown argument {arg:?}")),
+                    None => Ok(Command::Daemon),
+                }
+            }
+            Some("msg") => {
+                args.next();
+                let message: Vec<String> = args.collect();
+                if message.is_empty() {
+                    return Err("msg needs a message, show or cancel".to_string());
+                }
+                Ok(Command::Msg(message))
+            }
+            Some("preview") => {
+                args.next();
+                PreviewArgs::parse(args).map(Command::Preview)
+            }
+            _ => Args::parse(args).map(Command::Run),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Args {
+    pub action: Option<Action>,
+    pub mode: Mode,
+    pub format: Option<String>,
+    pub output: Option<OutputTarget>,
+    /// Typed after clicking, with the escapes already replaced
+    pub text: Option<String>,
+    pub repeat_last: bool,
+    pub help: bool,
+}
+
+impl Args {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args::default();
+        // The mode flags that were given, which only make sense on their own
+        let mut modes: Vec<(Mode, String)> = Vec::new();
+        while let Some(arg) = args.next() {
+            // Accept both `--flag value` and `--flag=value`
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value)),
+                _ => (arg.clone(), None),
+            };
+            let mut value = |name: &str| {
+                inline_value
+                    .map(str::to_string)
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{name} needs a value"))
+            };
+            match flag.as_str() {
+                "-a" | "--action" => {
+                    let name = value("--action")?;
+                    parsed.action = Some(Action::parse(&name).ok_or_else(|| {
+                        format!("unknown action {name:?}, expected {}", Action::NAMES)
+                    })?);
+                }
+                "-d" | "--drag" => modes.push((Mode::Drag, flag)),
+                "-s" | "--scroll" => modes.push((Mode::Scroll, flag)),
+                "-n" | "--normal" => modes.push((Mode::Normal, flag)),
+                "-p" | "--print" => modes.push((Mode::Print, flag)),
+                "-r" | "--region" => modes.push((Mode::Region, flag)),
+                "-t" | "--type" => parsed.text = Some(unescape(&value("--type")?)?),
+                "-f" | "--format" => parsed.format = Some(value("--format")?),
+                "-o" | "--output" => parsed.output = Some(OutputTarget::parse(&value("--output")?)),
+                "--repeat-last" => parsed.repeat_last = true,
+                "-h" | "--help" => parsed.help = true,
+                _ => return Err(format!("unknown argument {arg:?}")),
+            }
+        }
+        if let Some((mode, first)) = modes.first() {
+            if let Some((_, other)) = modes.iter().find(|(other, _)| other != mode) {
+                return Err(format!("{first} and {other} can't be used together"));
+            }
+            parsed.mode = *mode;
+        }
+        if parsed.text.is_some() && !matches!(parsed.mode, Mode::Click | Mode::Normal) {
+            return Err("--type only works when clicking".to_string());
+        }
+        Ok(parsed)
+    }
+
+    pub fn format(&self) -> &str {
+        match (&self.format, self.mode) {
+            (Some(format), _) => format,
+            (None, Mode::Region) => "%x,%y %wx%h",
+            (None, _) => "%x,%y",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PreviewArgs {
+    pub path: PathBuf,
+    pub size: (usize, usize),
+    pub scale: f64,
+    pub keys: String,
+}
+
+impl PreviewArgs {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut path = None;
+        let mut size = (1920, 1080);
+        let mut scale = 1.0;
+        let mut keys = String::new();
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value)),
+                _ => (arg.clone(), None),
+            };
+            let mut value = |name: &str| {
+                inline_value
+                    .map(str::to_string)
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{name} needs a value"))
+            };
+            match flag.as_str() {
+                "--size" => {
+                    let value = value("--size")?;
+                    size = value
+                        .split_once('x')
+                        .and_then(|(width, height)| {
+                            Some((width.parse().ok()?, height.parse().ok()?))
+                        })
+                        .filter(|&(width, height)| width > 0 && height > 0)
+                        .ok_or_else(|| {
+                            format!("invalid size {value:?}, expected e.g. 1920x1080")
+                        })?;
+                }
+                "--scale" => {
+                    let value = value("--scale")?;
+                    scale = value
+                        .parse()
+                        .ok()
+                        .filter(|scale: &f64| *scale > 0.0)
+                        .ok_or_else(|| format!("invalid scale {value:?}, expected e.g. 1.5"))?;
+                }
+                "--keys" => keys = value("--keys")?,
+                _ if path.is_none() && !arg.starts_with('-') => path = Some(PathBuf::from(arg)),
+                _ => return Err(format!("unknown argument {arg:?}")),
+            }
+        }
+        Ok(Self {
+            path: path.ok_or("preview needs a file to write to")?,
+            size,
+            scale,
+            keys,
+        })
+    }
+}
+
+/// Replaces the escapes described in the usage text for `--type`.
+fn unescape(text: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('\\') => out.push('\\'),
+            Some(other) => {
+                return Err(format!(
+                    "unknown escape \\{other} in {text:?}, expected \\n, \\t or \\\\"
+                ));
+            }
+            None => return Err(format!("{text:?} ends with a lone \\")),
+        }
+    }
+    Ok(out)
+}
+
+/// Expands the placeholders described in the usage text for `--format`.
+pub fn format_selection(
+    format: &str,
+    (x, y): (i32, i32),
+    (width, height): (u32, u32),
+    output: &str,
+) -> String {
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('x') => out.push_str(&x.to_string()),
+            Some('y') => out.push_str(&y.to_string()),
+            Some('w') => out.push_str(&width.to_string()),
+            Some('h') => out.push_str(&height.to_string()),
+            Some('o') => out.push_str(output),
+            Some('%') => out.push('%'),
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn formats_selections() {
+        let format = |format| format_selection(format, (-10, 20), (300, 40), "DP-1");
+        assert_eq!(format("%x,%y %wx%h"), "-10,20 300x40");
+        assert_eq!(format("%o: %x %y"), "DP-1: -10 20");
+        assert_eq!(format("100%%"), "100%");
+        assert_eq!(format("%%x"), "%x");
+        // Unknown placeholders and a trailing % are kept as they are
+        assert_eq!(format("%q %"), "%q %");
+        assert_eq!(format("é%x"), "é-10");
+        assert_eq!(format(""), "");
+    }
+
+    #[test]
+    fn unescapes_text() {
+        assert_eq!(unescape("plain"), Ok("plain".to_string()));
+        assert_eq!(unescape(r"a\nb\tc\\d"), Ok("a\nb\tc\\d".to_string()));
+        assert_eq!(unescape(r"\\n"), Ok(r"\n".to_string()));
+        assert!(unescape(r"\x").is_err());
+        assert!(unescape(r"trailing\").is_err());
+    }
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!(parse(&[]).unwrap().mode, Mode::Click);
+        assert_eq!(parse(&["--drag"]).unwrap().mode, Mode::Drag);
+        assert_eq!(parse(&["-p", "--print"]).unwrap().mode, Mode::Print);
+        assert_eq!(
+            parse(&["--drag", "--print"]).unwrap_err(),
+            "--drag and --print can't be used together"
+        );
+        assert_eq!(
+            parse(&["-r", "-a", "left", "-n"]).unwrap_err(),
+            "-r and -n can't be used together"
+        );
+        assert!(parse(&["--print", "--type", "x"]).is_err());
+        assert_eq!(
+            parse(&["--normal", "--type=a\\n"]).unwrap().text.as_deref(),
+            Some("a\n")
+        );
+    }
+
+    #[test]
+    fn default_formats() {
+        assert_eq!(parse(&["--print"]).unwrap().format(), "%x,%y");
+        assert_eq!(parse(&["--region"]).unwrap().format(), "%x,%y %wx%h");
+        assert_eq!(parse(&["--region", "-f", "%o"]).unwrap().format(), "%o");
+    }
+}
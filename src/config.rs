@@ -0,0 +1,1005 @@
+use std::{fmt, fs, io, path::PathBuf};
+
+use cosmic_text::Family;
+use serde::Deserialize;
+use wayland_client::protocol::wl_pointer::Axis;
+
+use crate::{
+    actions::Action, hints::LabelStyle, keyboard_utils::Modifiers, output_utils::OutputTarget,
+    pointer::BackendKind, render_utils::alpha_multiply,
+};
+
+/// Settings read from `$XDG_CONFIG_HOME/nomouse/config.toml`. Every key is
+/// optional, missing ones keep the defaults below.
+///
+/// ```toml
+/// output = "focused"
+/// backend = "auto"
+///
+/// [grid]
+/// rows = 16
+/// columns = 12
+/// final_rows = 3
+/// final_columns = 8
+///
+/// [hints]
+/// alphabet = "qweruiopasdfjkl;zxcvm,./"
+/// labels = "grid"
+///
+/// [colors]
+/// background = "#ffffff"
+/// opacity = 0.5
+/// border_light = "#ffffff"
+/// border_dark = "#000000"
+/// text = "#000000"
+/// output_label = "#ffffff"
+/// typed = "#0050b0"
+///
+/// [font]
+/// family = "sans-serif"
+/// size = 14.0
+/// line_height = 10.0
+///
+/// [click]
+/// action = "left"
+/// shift_action = "right"
+/// ctrl_action = "middle"
+/// alt_action = "double"
+///
+/// [scroll]
+/// up = "k"
+/// down = "j"
+/// left = "h"
+/// right = "l"
+/// step = 15.0
+///
+/// [normal]
+/// up = "k"
+/// down = "j"
+/// left = "h"
+/// right = "l"
+/// step = 10.0
+/// acceleration = 4.0
+/// max_step = 100.0
+/// slow_step = 1.0
+///
+/// [marks]
+/// set = "M"
+/// jump = "'"
+/// ```
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub output: OutputTarget,
+    pub backend: BackendKind,
+    pub grid: GridConfig,
+    pub hints: HintsConfig,
+    pub colors: ColorsConfig,
+    pub font: FontConfig,
+    pub click: ClickConfig,
+    pub scroll: ScrollConfig,
+    pub normal: NormalConfig,
+    pub marks: MarksConfig,
+}
+
+#[derive(Debug, Clone)]
+pub struct GridConfig {
+    pub rows: usize,
+    pub columns: usize,
+    pub final_rows: usize,
+    pub final_columns: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct HintsConfig {
+    pub alphabet: Vec<char>,
+    pub labels: LabelStyle,
+}
+
+/// Colors are straight (not premultiplied) `(r, g, b, a)`.
+#[derive(Debug, Clone)]
+pub struct ColorsConfig {
+    pub background: (u8, u8, u8, u8),
+    pub opacity: f32,
+    pub border_light: (u8, u8, u8, u8),
+    pub border_dark: (u8, u8, u8, u8),
+    pub text: (u8, u8, u8, u8),
+    pub output_label: (u8, u8, u8, u8),
+    /// The keys of a label that were typed already
+    pub typed: (u8, u8, u8, u8),
+}
+
+#[derive(Debug, Clone)]
+pub struct FontConfig {
+    pub family: String,
+    pub size: f32,
+    pub line_height: f32,
+}
+
+/// `action` is used unless one of the modifiers is held while typing the
+/// last label.
+#[derive(Debug, Clone)]
+pub struct ClickConfig {
+    pub action: Action,
+    pub shift_action: Action,
+    pub ctrl_action: Action,
+    pub alt_action: Action,
+}
+
+/// Keys used in scroll mode, `step` is the distance of one scroll step in
+/// surface coordinates.
+#[derive(Debug, Clone)]
+pub struct ScrollConfig {
+    pub up: char,
+    pub down: char,
+    pub left: char,
+    pub right: char,
+    pub step: f64,
+}
+
+/// Keys and distances of `--normal`, in surface coordinates. Steps start at
+/// `step` and grow by `acceleration` times `step` for every second the keys
+/// repeat, up to `max_step`. They are `slow_step` while shift is held.
+#[derive(Debug, Clone)]
+pub struct NormalConfig {
+    pub up: char,
+    pub down: char,
+    pub left: char,
+    pub right: char,
+    pub step: f64,
+    pub acceleration: f64,
+    pub max_step: f64,
+    pub slow_step: f64,
+}
+
+/// `set` followed by a character saves the selected position as a mark of
+/// that name, `jump` followed by one selects the mark.
+#[derive(Debug, Clone, Default)]
+pub struct MarksConfig {
+    pub set: char,
+    pub jump: char,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            output: OutputTarget::Focused,
+            backend: BackendKind::Auto,
+            grid: GridConfig {
+                rows: 16,
+                columns: 12,
+                final_rows: 3,
+                final_columns: 8,
+            },
+            hints: HintsConfig {
+                alphabet: "qweruiopasdfjkl;zxcvm,./".chars().collect(),
+                labels: LabelStyle::Grid,
+            },
+            colors: ColorsConfig {
+                background: (255, 255, 255, 255),
+                opacity: 0.5,
+                border_light: (255, 255, 255, 255),
+                border_dark: (0, 0, 0, 255),
+                text: (0, 0, 0, 255),
+                output_label: (255, 255, 255, 255),
+                typed: (0, 80, 176, 255),
+            },
+            font: FontConfig {
+                family: "sans-serif".into(),
+                size: 14.0,
+                line_height: 10.0,
+            },
+            click: ClickConfig {
+                action: Action::Left,
+                shift_action: Action::Right,
+                ctrl_action: Action::Middle,
+                alt_action: Action::Double,
+            },
+            scroll: ScrollConfig {
+                up: 'k',
+                down: 'j',
+                left: 'h',
+                right: 'l',
+                step: 15.0,
+            },
+            normal: NormalConfig {
+                up: 'k',
+                down: 'j',
+                left: 'h',
+                right: 'l',
+                step: 10.0,
+                acceleration: 4.0,
+                max_step: 100.0,
+                slow_step: 1.0,
+            },
+            marks: MarksConfig {
+                // m is one of the default hint characters
+                set: 'M',
+                jump: '\'',
+            },
+        }
+    }
+}
+
+impl ColorsConfig {
+    /// The background with `opacity` applied, premultiplied like the
+    /// framebuffer expects.
+    pub fn background_pixel(&self) -> (u8, u8, u8, u8) {
+        let (r, g, b, a) = self.background;
+        alpha_multiply((r, g, b, (a as f32 * self.opacity).round() as u8))
+    }
+}
+
+impl ClickConfig {
+    /// The action picked by the modifiers held during the last key press.
+    pub fn modifier_action(&self, modifiers: Modifiers) -> Option<Action> {
+        if modifiers.shift {
+            Some(self.shift_action)
+        } else if modifiers.ctrl {
+            Some(self.ctrl_action)
+        } else if modifiers.alt {
+            Some(self.alt_action)
+        } else {
+            None
+        }
+    }
+}
+
+impl ScrollConfig {
+    /// The axis a key scrolls along and in which direction.
+    pub fn axis_for(&self, key: char) -> Option<(Axis, i32)> {
+        match key {
+            k if k == self.up => Some((Axis::VerticalScroll, -1)),
+            k if k == self.down => Some((Axis::VerticalScroll, 1)),
+            k if k == self.left => Some((Axis::HorizontalScroll, -1)),
+            k if k == self.right => Some((Axis::HorizontalScroll, 1)),
+            _ => None,
+        }
+    }
+}
+
+impl FontConfig {
+    pub fn family(&self) -> Family<'_> {
+        match self.family.as_str() {
+            "serif" => Family::Serif,
+            "sans-serif" => Family::SansSerif,
+            "monospace" => Family::Monospace,
+            "cursive" => Family::Cursive,
+            "fantasy" => Family::Fantasy,
+            name => Family::Name(name),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Syntax { line: usize, message: String },
+    Invalid { key: String, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "could not read {}: {err}", path.display()),
+            ConfigError::Syntax { line, message } => write!(f, "line {line}: {message}"),
+            ConfigError::Invalid { key, message } => write!(f, "{key}: {message}"),
+        }
+    }
+}
+
+fn invalid(key: &str, message: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid {
+        key: key.into(),
+        message: message.into(),
+    }
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("nomouse").join("config.toml"))
+}
+
+impl Config {
+    /// Loads the config file, falling back to the defaults when there is none.
+    pub fn load() -> Result<Self, ConfigError> {
+        let Some(path) = config_path() else {
+            return Ok(Self::default());
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => Self::parse(&contents),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(ConfigError::Io(path, err)),
+        }
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let file: File = toml::from_str(contents).map_err(|err| syntax_error(contents, &err))?;
+        let mut config = Self::default();
+        config.apply(file)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Takes over the keys set in `file`, checking each of them.
+    fn apply(&mut self, file: File) -> Result<(), ConfigError> {
+        let File {
+            output,
+            backend,
+            grid,
+            hints,
+            colors,
+            font,
+            click,
+            scroll,
+            normal,
+            marks,
+        } = file;
+        if let Some(output) = output {
+            self.output = OutputTarget::parse(&output);
+        }
+        set(&mut self.backend, "backend", backend, |key, name| {
+            BackendKind::parse(&name).ok_or_else(|| {
+                invalid(
+                    key,
+                    format!("unknown backend {name:?}, expected {}", BackendKind::NAMES),
+                )
+            })
+        })?;
+        set(
+            &mut self.grid.rows,
+            "grid.rows",
+            grid.rows,
+            positive_integer,
+        )?;
+        set(
+            &mut self.grid.columns,
+            "grid.columns",
+            grid.columns,
+            positive_integer,
+        )?;
+        set(
+            &mut self.grid.final_rows,
+            "grid.final_rows",
+            grid.final_rows,
+            positive_integer,
+        )?;
+        set(
+            &mut self.grid.final_columns,
+            "grid.final_columns",
+            grid.final_columns,
+            positive_integer,
+        )?;
+        if let Some(alphabet) = hints.alphabet {
+            self.hints.alphabet = alphabet.chars().collect();
+        }
+        set(
+            &mut self.hints.labels,
+            "hints.labels",
+            hints.labels,
+            |key, name| {
+                LabelStyle::parse(&name).ok_or_else(|| {
+                    invalid(
+                        key,
+                        format!("unknown labels {name:?}, expected {}", LabelStyle::NAMES),
+                    )
+                })
+            },
+        )?;
+        set(
+            &mut self.colors.background,
+            "colors.background",
+            colors.background,
+            color,
+        )?;
+        set(
+            &mut self.colors.opacity,
+            "colors.opacity",
+            colors.opacity,
+            |key, opacity| {
+                if !(0.0..=1.0).contains(&opacity) {
+                    return Err(invalid(key, "must be between 0.0 and 1.0"));
+                }
+                Ok(opacity as f32)
+            },
+        )?;
+        set(
+            &mut self.colors.border_light,
+            "colors.border_light",
+            colors.border_light,
+            color,
+        )?;
+        set(
+            &mut self.colors.border_dark,
+            "colors.border_dark",
+            colors.border_dark,
+            color,
+        )?;
+        set(&mut self.colors.text, "colors.text", colors.text, color)?;
+        set(
+            &mut self.colors.output_label,
+            "colors.output_label",
+            colors.output_label,
+            color,
+        )?;
+        set(&mut self.colors.typed, "colors.typed", colors.typed, color)?;
+        if let Some(family) = font.family {
+            self.font.family = family;
+        }
+        set(&mut self.font.size, "font.size", font.size, |key, size| {
+            positive_float(key, size).map(|size| size as f32)
+        })?;
+        set(
+            &mut self.font.line_height,
+            "font.line_height",
+            font.line_height,
+            |key, height| positive_float(key, height).map(|height| height as f32),
+        )?;
+        set(&mut self.click.action, "click.action", click.action, action)?;
+        set(
+            &mut self.click.shift_action,
+            "click.shift_action",
+            click.shift_action,
+            action,
+        )?;
+        set(
+            &mut self.click.ctrl_action,
+            "click.ctrl_action",
+            click.ctrl_action,
+            action,
+        )?;
+        set(
+            &mut self.click.alt_action,
+            "click.alt_action",
+            click.alt_action,
+            action,
+        )?;
+        set(&mut self.scroll.up, "scroll.up", scroll.up, key)?;
+        set(&mut self.scroll.down, "scroll.down", scroll.down, key)?;
+        set(&mut self.scroll.left, "scroll.left", scroll.left, key)?;
+        set(&mut self.scroll.right, "scroll.right", scroll.right, key)?;
+        set(
+            &mut self.scroll.step,
+            "scroll.step",
+            scroll.step,
+            positive_float,
+        )?;
+        set(&mut self.normal.up, "normal.up", normal.up, key)?;
+        set(&mut self.normal.down, "normal.down", normal.down, key)?;
+        set(&mut self.normal.left, "normal.left", normal.left, key)?;
+        set(&mut self.normal.right, "normal.right", normal.right, key)?;
+        set(
+            &mut self.normal.step,
+            "normal.step",
+            normal.step,
+            positive_float,
+        )?;
+        set(
+            &mut self.normal.acceleration,
+            "normal.acceleration",
+            normal.acceleration,
+            |key, acceleration| {
+                if acceleration < 0.0 {
+                    return Err(invalid(key, "can not be negative"));
+                }
+                Ok(acceleration)
+            },
+        )?;
+        set(
+            &mut self.normal.max_step,
+            "normal.max_step",
+            normal.max_step,
+            positive_float,
+        )?;
+        set(
+            &mut self.normal.slow_step,
+            "normal.slow_step",
+            normal.slow_step,
+            positive_float,
+        )?;
+        set(&mut self.marks.set, "marks.set", marks.set, key)?;
+        set(&mut self.marks.jump, "marks.jump", marks.jump, key)?;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let alphabet = &self.hints.alphabet;
+        let mut unique = alphabet.clone();
+        unique.sort();
+        unique.dedup();
+        if unique.len() != alphabet.len() {
+            return Err(invalid("hints.alphabet", "contains duplicate characters"));
+        }
+        if let Some(c) = alphabet
+            .iter()
+            .find(|c| c.is_whitespace() || c.is_control())
+        {
+            return Err(invalid(
+                "hints.alphabet",
+                format!("{c:?} can not be typed as a hint"),
+            ));
+        }
+        if alphabet.len() < 2 {
+            return Err(invalid(
+                "hints.alphabet",
+                "needs at least two hint characters",
+            ));
+        }
+        let scroll = &self.scroll;
+        let scroll_keys = [scroll.up, scroll.down, scroll.left, scroll.right];
+        if scroll_keys
+            .iter()
+            .enumerate()
+            .any(|(i, key)| scroll_keys[..i].contains(key))
+        {
+            return Err(invalid(
+                "scroll",
+                "up, down, left and right must be different keys",
+            ));
+        }
+        let normal = &self.normal;
+        let normal_keys = [normal.up, normal.down, normal.left, normal.right];
+        if normal_keys
+            .iter()
+            .enumerate()
+            .any(|(i, key)| normal_keys[..i].contains(key))
+        {
+            return Err(invalid(
+                "normal",
+                "up, down, left and right must be different keys",
+            ));
+        }
+        for (key, c) in [
+            ("marks.set", self.marks.set),
+            ("marks.jump", self.marks.jump),
+        ] {
+            if alphabet.contains(&c) {
+                return Err(invalid(key, format!("{c:?} is already a hint character")));
+            }
+        }
+        if self.marks.set == self.marks.jump {
+            return Err(invalid("marks", "set and jump must be different keys"));
+        }
+        // Shortest labels are made up for any number of cells
+        if self.hints.labels == LabelStyle::Grid {
+            if self.grid.rows > alphabet.len() {
+                return Err(invalid(
+                    "grid.rows",
+                    format!(
+                        "needs at most as many rows as hint characters ({})",
+                        alphabet.len()
+                    ),
+                ));
+            }
+            if self.grid.columns > alphabet.len() {
+                return Err(invalid(
+                    "grid.columns",
+                    format!(
+                        "needs at most as many columns as hint characters ({})",
+                        alphabet.len()
+                    ),
+                ));
+            }
+        }
+        if self.grid.final_rows * self.grid.final_columns > alphabet.len() {
+            return Err(invalid(
+                "grid.final_columns",
+                format!(
+                    "final_rows * final_columns can not exceed the number of hint characters ({})",
+                    alphabet.len()
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The config file as written, before its values are checked. Unknown keys
+/// and values of the wrong type are rejected while parsing.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct File {
+    output: Option<String>,
+    backend: Option<String>,
+    grid: GridFile,
+    hints: HintsFile,
+    colors: ColorsFile,
+    font: FontFile,
+    click: ClickFile,
+    scroll: ScrollFile,
+    normal: NormalFile,
+    marks: MarksFile,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct GridFile {
+    rows: Option<i64>,
+    columns: Option<i64>,
+    final_rows: Option<i64>,
+    final_columns: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct HintsFile {
+    alphabet: Option<String>,
+    labels: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ColorsFile {
+    background: Option<String>,
+    opacity: Option<f64>,
+    border_light: Option<String>,
+    border_dark: Option<String>,
+    text: Option<String>,
+    output_label: Option<String>,
+    typed: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FontFile {
+    family: Option<String>,
+    size: Option<f64>,
+    line_height: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ClickFile {
+    action: Option<String>,
+    shift_action: Option<String>,
+    ctrl_action: Option<String>,
+    alt_action: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ScrollFile {
+    up: Option<String>,
+    down: Option<String>,
+    left: Option<String>,
+    right: Option<String>,
+    step: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct NormalFile {
+    up: Option<String>,
+    down: Option<String>,
+    left: Option<String>,
+    right: Option<String>,
+    step: Option<f64>,
+    acceleration: Option<f64>,
+    max_step: Option<f64>,
+    slow_step: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct MarksFile {
+    set: Option<String>,
+    jump: Option<String>,
+}
+
+/// A TOML error, with the line it points at.
+fn syntax_error(contents: &str, err: &toml::de::Error) -> ConfigError {
+    let line = err
+        .span()
+        .map_or(1, |span| contents[..span.start].matches('\n').count() + 1);
+    ConfigError::Syntax {
+        line,
+        message: err.message().to_string(),
+    }
+}
+
+/// Sets `target` to `value` once `check` accepted it, when `key` is set.
+fn set<T, U>(
+    target: &mut U,
+    key: &str,
+    value: Option<T>,
+    check: impl FnOnce(&str, T) -> Result<U, ConfigError>,
+) -> Result<(), ConfigError> {
+    if let Some(value) = value {
+        *target = check(key, value)?;
+    }
+    Ok(())
+}
+
+fn positive_integer(key: &str, value: i64) -> Result<usize, ConfigError> {
+    if value < 1 {
+        return Err(invalid(key, "must be at least 1"));
+    }
+    Ok(value as usize)
+}
+
+fn positive_float(key: &str, value: f64) -> Result<f64, ConfigError> {
+    if value <= 0.0 || !value.is_finite() {
+        return Err(invalid(key, "must be greater than 0"));
+    }
+    Ok(value)
+}
+
+/// A single character that is typed to trigger something.
+fn key(key: &str, value: String) -> Result<char, ConfigError> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if !c.is_whitespace() && !c.is_control() => Ok(c),
+        _ => Err(invalid(
+            key,
+            format!("expected a single character, found {value:?}"),
+        )),
+    }
+}
+
+fn action(key: &str, name: String) -> Result<Action, ConfigError> {
+    Action::parse(&name).ok_or_else(|| {
+        invalid(
+            key,
+            format!("unknown action {name:?}, expected {}", Action::NAMES),
+        )
+    })
+}
+
+/// `#rrggbb` or `#rrggbbaa`
+fn color(key: &str, value: String) -> Result<(u8, u8, u8, u8), ConfigError> {
+    let hex = value
+        .strip_prefix('#')
+        .filter(|hex| (hex.len() == 6 || hex.len() == 8) && hex.is_ascii())
+        .ok_or_else(|| {
+            invalid(
+                key,
+                format!("expected #rrggbb or #rrggbbaa, found {value:?}"),
+            )
+        })?;
+    let channel = |i: usize| {
+        u8::from_str_radix(&hex[i..i + 2], 16)
+            .map_err(|_| invalid(key, format!("{value:?} is not a valid hex color")))
+    };
+    let alpha = if hex.len() == 8 { channel(6)? } else { 255 };
+    Ok((channel(0)?, channel(2)?, channel(4)?, alpha))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(contents: &str) -> String {
+        Config::parse(contents).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_every_kind_of_value() {
+        let config = Config::parse(
+            r##"
+            # Comments are ignored, also after values
+            output = "DP-1" # "quoted # too"
+            backend = 'uinput'
+
+            [grid]
+            rows = 1_0
+            columns = 8
+            final_rows = 2
+            final_columns = 4
+
+            [hints]
+            alphabet = "asdfjklgh;\""
+            labels = "shortest"
+
+            [colors]
+            background = "#10203080"
+            opacity = 1
+            typed = "#A0b0C0"
+
+            [font]
+            family = "monospace"
+            size = 12.5
+
+            [click]
+            shift_action = "none"
+
+            [scroll]
+            step = 20
+
+            [normal]
+            up = "w"
+            acceleration = 0
+
+            [marks]
+            set = "#"
+            "##,
+        )
+        .unwrap();
+        assert_eq!(config.output, OutputTarget::Name("DP-1".into()));
+        assert_eq!(config.backend, BackendKind::Uinput);
+        assert_eq!(config.grid.rows, 10);
+        assert_eq!(config.grid.final_columns, 4);
+        assert_eq!(
+            config.hints.alphabet,
+            "asdfjklgh;\"".chars().collect::<Vec<_>>()
+        );
+        assert_eq!(config.hints.labels, LabelStyle::Shortest);
+        assert_eq!(config.colors.background, (16, 32, 48, 128));
+        assert_eq!(config.colors.opacity, 1.0);
+        assert_eq!(config.colors.typed, (160, 176, 192, 255));
+        assert_eq!(config.font.family, "monospace");
+        assert_eq!(config.font.size, 12.5);
+        assert_eq!(config.click.shift_action, Action::None);
+        assert_eq!(config.scroll.step, 20.0);
+        assert_eq!(config.normal.up, 'w');
+        assert_eq!(config.normal.acceleration, 0.0);
+        assert_eq!(config.marks.set, '#');
+        // Whatever isn't set keeps its default
+        assert_eq!(config.click.action, Action::Left);
+        assert_eq!(config.marks.jump, '\'');
+    }
+
+    #[test]
+    fn empty_config_is_the_default() {
+        let config = Config::parse("\n# nothing here\n").unwrap();
+        let default = Config::default();
+        assert_eq!(config.hints.alphabet, default.hints.alphabet);
+        assert_eq!(config.grid.rows, default.grid.rows);
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn parses_the_rest_of_toml() {
+        let config = Config::parse(
+            r#"
+            grid = { rows = 4, columns = 6 }
+            hints.alphabet = """
+abcdefghijklm\
+            nopqrstuvwxyz"""
+            colors.text = "\U00000023ff0000"
+            "#,
+        )
+        .unwrap();
+        assert_eq!((config.grid.rows, config.grid.columns), (4, 6));
+        assert_eq!(config.hints.alphabet, ('a'..='z').collect::<Vec<_>>());
+        assert_eq!(config.colors.text, (255, 0, 0, 255));
+    }
+
+    #[test]
+    fn rejects_invalid_syntax() {
+        assert!(error("[grid").starts_with("line 1: invalid table header"));
+        assert!(error("\nrows").starts_with("line 2: expected `.`, `=`"));
+        assert!(error("output = \"all").starts_with("line 1: invalid basic string"));
+        assert!(error(r#"output = "\q""#).starts_with("line 1: invalid escape sequence"));
+        assert!(error("output = all").starts_with("line 1: invalid string"));
+        assert_eq!(
+            error("output = \"all\"\n\noutput = \"all\""),
+            "line 3: duplicate key `output` in document root"
+        );
+        assert_eq!(
+            error("output = 1"),
+            "line 1: invalid type: integer `1`, expected a string"
+        );
+        assert_eq!(
+            error("[grid]\nrows = 1.5"),
+            "line 2: invalid type: floating point `1.5`, expected i64"
+        );
+        assert_eq!(
+            error("[colors]\nopacity = true"),
+            "line 2: invalid type: boolean `true`, expected f64"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(error("outputs = \"all\"").starts_with("line 1: unknown field `outputs`"));
+        assert!(error("[grid]\ncells = 3").starts_with("line 2: unknown field `cells`"));
+        assert!(error("[gird]\nrows = 3").starts_with("line 1: unknown field `gird`"));
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert_eq!(
+            error("backend = \"x11\""),
+            "backend: unknown backend \"x11\", expected auto, wlr, ei or uinput"
+        );
+        assert_eq!(error("[grid]\nrows = 0"), "grid.rows: must be at least 1");
+        assert_eq!(
+            error("[scroll]\nstep = inf"),
+            "scroll.step: must be greater than 0"
+        );
+        assert_eq!(
+            error("[hints]\nlabels = \"short\""),
+            "hints.labels: unknown labels \"short\", expected grid or shortest"
+        );
+        assert_eq!(
+            error("[colors]\nopacity = 1.5"),
+            "colors.opacity: must be between 0.0 and 1.0"
+        );
+        assert_eq!(
+            error("[colors]\ntext = \"#fff\""),
+            "colors.text: expected #rrggbb or #rrggbbaa, found \"#fff\""
+        );
+        assert_eq!(
+            error("[colors]\ntext = \"#gg0000\""),
+            "colors.text: \"#gg0000\" is not a valid hex color"
+        );
+        assert_eq!(
+            error("[font]\nsize = 0"),
+            "font.size: must be greater than 0"
+        );
+        assert_eq!(
+            error("[click]\naction = \"dbl\""),
+            "click.action: unknown action \"dbl\", expected left, right, middle, double, triple or none"
+        );
+        assert_eq!(
+            error("[scroll]\nup = \"kk\""),
+            "scroll.up: expected a single character, found \"kk\""
+        );
+        assert_eq!(
+            error("[scroll]\nup = \" \""),
+            "scroll.up: expected a single character, found \" \""
+        );
+        assert_eq!(
+            error("[normal]\nacceleration = -1"),
+            "normal.acceleration: can not be negative"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_combinations() {
+        assert_eq!(
+            error("[hints]\nalphabet = \"abca\""),
+            "hints.alphabet: contains duplicate characters"
+        );
+        assert_eq!(
+            error("[hints]\nalphabet = \"ab c\""),
+            "hints.alphabet: ' ' can not be typed as a hint"
+        );
+        assert_eq!(
+            error("[scroll]\nup = \"j\""),
+            "scroll: up, down, left and right must be different keys"
+        );
+        assert_eq!(
+            error("[normal]\nleft = \"l\""),
+            "normal: up, down, left and right must be different keys"
+        );
+        assert_eq!(
+            error("[marks]\nset = \"q\""),
+            "marks.set: 'q' is already a hint character"
+        );
+        assert_eq!(
+            error("[marks]\njump = \"q\""),
+            "marks.jump: 'q' is already a hint character"
+        );
+        assert_eq!(
+            error("[marks]\njump = \"M\""),
+            "marks: set and jump must be different keys"
+        );
+        assert_eq!(
+            error(
+                "[hints]\nalphabet = \"a\"\nlabels = \"shortest\"\n[grid]\nfinal_rows = 1\nfinal_columns = 1"
+            ),
+            "hints.alphabet: needs at least two hint characters"
+        );
+        assert_eq!(
+            error("[hints]\nalphabet = \"\""),
+            "hints.alphabet: needs at least two hint characters"
+        );
+        assert_eq!(
+            error("[grid]\nrows = 25"),
+            "grid.rows: needs at most as many rows as hint characters (24)"
+        );
+        assert_eq!(
+            error("[grid]\ncolumns = 25"),
+            "grid.columns: needs at most as many columns as hint characters (24)"
+        );
+        assert_eq!(
+            error("[grid]\nfinal_rows = 5\nfinal_columns = 5"),
+            "grid.final_columns: final_rows * final_columns can not exceed the number of hint characters (24)"
+        );
+        // Shortest labels don't need a row or column per character
+        assert!(Config::parse("[hints]\nlabels = \"shortest\"\n[grid]\nrows = 30").is_ok());
+    }
+}
@@ -0,0 +1,491 @@
+use cosmic_text::{FontSystem, SwashCache};
+use nix::{
+    errno::Errno,
+    poll::{PollFd, PollFlags, poll},
+    sys::{
+        signal::{SigSet, Signal},
+        signalfd::{SfdFlags, SignalFd},
+    },
+};
+use std::{
+    env, fs,
+    io::{self, Read, Write},
+    mem,
+    os::{
+        fd::AsFd,
+        unix::{
+            fs::FileTypeExt,
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use wayland_client::{EventQueue, backend::WaylandError};
+
+use crate::{
+    app::{AppData, Wayland},
+    cli::{Args, EXIT_ERROR, EXIT_USAGE, USAGE},
+    config::Config,
+    session::{self, Outcome, Session},
+};
+
+/// How long a caller has to send its whole request, before it is hung up on.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A message sent with `nomouse msg`.
+enum Request {
+    Show(Args),
+    Cancel,
+}
+
+impl Request {
+    fn parse(message: &[String]) -> Result<Self, String> {
+        match message.split_first() {
+            Some((name, args)) if name == "show" => {
+                Args::parse(args.iter().cloned()).map(Request::Show)
+            }
+            Some((name, [])) if name == "cancel" => Ok(Request::Cancel),
+            Some((name, [arg, ..])) if name == "cancel" => Err(format!("unknown argument {arg:?}")),
+            Some((name, _)) => Err(format!("unknown message {name:?}, expected show or cancel")),
+            None => Err("empty message".to_string()),
+        }
+    }
+}
+
+/// The socket of the daemon for the current Wayland display, so daemons on
+/// different displays don't get in each other's way.
+fn socket_path() -> Result<PathBuf, String> {
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR").ok_or("XDG_RUNTIME_DIR is not set")?;
+    let display = env::var_os("WAYLAND_DISPLAY").unwrap_or_else(|| "wayland-0".into());
+    let display = Path::new(&display)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    Ok(Path::new(&runtime_dir).join(format!("nomouse-{display}.sock")))
+}
+
+/// Binds the socket at `path`, unless a daemon is listening on it already.
+/// A socket nobody is listening on was left behind by a daemon that didn't
+/// exit cleanly and is replaced.
+fn bind(path: &Path) -> Result<UnixListener, String> {
+    let in_path = |err| format!("{}: {err}", path.display());
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            return Err(format!("{} exists and isn't a socket", path.display()));
+        }
+        Ok(_) => match UnixStream::connect(path) {
+            Ok(_) => return Err(format!("a daemon is already running at {}", path.display())),
+            Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                fs::remove_file(path).map_err(in_path)?;
+            }
+            Err(err) => return Err(in_path(err)),
+        },
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(in_path(err)),
+    }
+    UnixListener::bind(path).map_err(in_path)
+}
+
+/// Removes the socket when the daemon exits, also when it panics, so the
+/// next one doesn't find it.
+struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// A caller whose request hasn't arrived completely yet. Read whenever more
+/// of it arrives, so a slow caller doesn't hold up a session being shown.
+struct PendingRequest {
+    stream: UnixStream,
+    request: Vec<u8>,
+    deadline: Instant,
+}
+
+impl PendingRequest {
+    fn new(stream: UnixStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        Ok(Self {
+            stream,
+            request: Vec::new(),
+            deadline: Instant::now() + REQUEST_TIMEOUT,
+        })
+    }
+
+    /// Reads what has arrived so far. True once the caller has sent all of
+    /// its request and shut down its side of the connection.
+    fn read(&mut self) -> io::Result<bool> {
+        let mut buf = [0; 4096];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Ok(true),
+                Ok(len) => self.request.extend_from_slice(&buf[..len]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Requests and responses are a list of strings, each one terminated by a
+/// nul byte. A request is the arguments after `nomouse msg`, a response is
+/// the exit status followed by what to print to stdout and stderr.
+fn encode(fields: &[impl AsRef<str>]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for field in fields {
+        bytes.extend_from_slice(field.as_ref().as_bytes());
+        bytes.push(0);
+    }
+    bytes
+}
+
+fn decode(bytes: &[u8]) -> Vec<String> {
+    let mut fields: Vec<String> = bytes
+        .split(|byte| *byte == 0)
+        .map(|field| String::from_utf8_lossy(field).into_owned())
+        .collect();
+    // Whatever follows the last terminator, empty unless cut off
+    fields.pop();
+    fields
+}
+
+fn respond(mut stream: UnixStream, status: i32, stdout: &str, stderr: &str) {
+    // The caller may be gone already, nothing to do about that
+    let _ = stream.write_all(&encode(&[&status.to_string(), stdout, stderr]));
+}
+
+/// Sends `message` to the daemon and prints its response. Returns the exit
+/// status to exit with.
+pub fn send(message: &[String]) -> i32 {
+    let result = socket_path().and_then(|path| {
+        let mut stream = UnixStream::connect(&path)
+            .map_err(|err| format!("can't connect to the daemon at {}: {err}", path.display()))?;
+        let mut response = Vec::new();
+        stream
+            .write_all(&encode(message))
+            .and_then(|_| stream.shutdown(std::net::Shutdown::Write))
+            .and_then(|_| stream.read_to_end(&mut response))
+            .map_err(|err| format!("daemon connection: {err}"))?;
+        Ok(decode(&response))
+    });
+    match result.as_deref() {
+        Ok([status, stdout, stderr]) => {
+            print!("{stdout}");
+            eprint!("{stderr}");
+            status.parse().unwrap_or(EXIT_ERROR)
+        }
+        Ok(_) => {
+            eprintln!("nomouse: invalid response from the daemon");
+            EXIT_ERROR
+        }
+        Err(err) => {
+            eprintln!("nomouse: {err}");
+            EXIT_ERROR
+        }
+    }
+}
+
+/// Runs the daemon until it fails or is stopped by a signal. Returns the
+/// exit status to exit with.
+pub fn run() -> i32 {
+    let (listener, _socket_file) = match socket_path().and_then(|path| {
+        let listener = bind(&path)?;
+        Ok((listener, SocketFile(path)))
+    }) {
+        Ok(bound) => bound,
+        Err(err) => {
+            eprintln!("nomouse: {err}");
+            return EXIT_ERROR;
+        }
+    };
+    let signals = match stop_signals() {
+        Ok(signals) => signals,
+        Err(err) => {
+            eprintln!("nomouse: can't handle signals: {err}");
+            return EXIT_ERROR;
+        }
+    };
+    if let Err(err) = listener.set_nonblocking(true) {
+        eprintln!("nomouse: {err}");
+        return EXIT_ERROR;
+    }
+    let (mut app, mut event_queue) = match AppData::connect() {
+        Ok(connected) => connected,
+        Err(err) => {
+            eprintln!("nomouse: {err}");
+            return EXIT_ERROR;
+        }
+    };
+    let mut font_system = FontSystem::new();
+    let mut swash_cache = SwashCache::new();
+    // The session being shown and the caller waiting for its outcome
+    let mut active: Option<(Session, UnixStream)> = None;
+    let mut pending: Vec<PendingRequest> = Vec::new();
+
+    loop {
+        event_queue.dispatch_pending(&mut app).unwrap();
+        event_queue.flush().unwrap();
+        let mut incoming = false;
+        let mut stopped = false;
+        let mut readable = vec![false; pending.len()];
+        if let Some(guard) = event_queue.prepare_read() {
+            let mut fds = vec![
+                PollFd::new(guard.connection_fd(), PollFlags::POLLIN),
+                PollFd::new(listener.as_fd(), PollFlags::POLLIN),
+                PollFd::new(signals.as_fd(), PollFlags::POLLIN),
+            ];
+            for request in &pending {
+                fds.push(PollFd::new(request.stream.as_fd(), PollFlags::POLLIN));
+            }
+            if let Some((_, stream)) = &active {
+                // Only interested in the caller hanging up
+                fds.push(PollFd::new(stream.as_fd(), PollFlags::empty()));
+            }
+            let deadline = pending
+                .iter()
+                .map(|request| request.deadline)
+                .chain(
+                    active
+                        .as_ref()
+                        .and_then(|(session, _)| session.next_update()),
+                )
+                .min();
+            match poll(&mut fds, session::poll_timeout(deadline)) {
+                Ok(_) | Err(Errno::EINTR) => {}
+                Err(err) => panic!("poll: {err}"),
+            }
+            let ready = |fd: &PollFd| fd.revents().is_some_and(|revents| !revents.is_empty());
+            let wayland = ready(&fds[0]);
+            incoming = ready(&fds[1]);
+            stopped = ready(&fds[2]);
+            for (i, readable) in readable.iter_mut().enumerate() {
+                *readable = ready(&fds[3 + i]);
+            }
+            let hung_up = fds.get(3 + pending.len()).is_some_and(ready);
+            drop(fds);
+            if hung_up && let Some((session, _)) = active.as_mut() {
+                // Killed while waiting, don't leave the overlay behind
+                session.cancel();
+            }
+            if wayland {
+                match guard.read() {
+                    Ok(_) => {}
+                    Err(WaylandError::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(err) => panic!("wayland connection: {err}"),
+                }
+            }
+        }
+        event_queue.dispatch_pending(&mut app).unwrap();
+
+        if stopped {
+            if let Some((mut session, stream)) = active.take() {
+                session.cancel();
+                if let Some(Outcome { status, stdout }) = session.update(
+                    &mut Wayland::new(&mut app, &mut event_queue),
+                    &mut font_system,
+                    &mut swash_cache,
+                ) {
+                    respond(stream, status, &stdout, "nomouse: the daemon stopped\n");
+                }
+            }
+            return 0;
+        }
+        let now = Instant::now();
+        for (mut request, readable) in mem::take(&mut pending).into_iter().zip(readable) {
+            let complete = match readable.then(|| request.read()) {
+                Some(Ok(complete)) => complete,
+                Some(Err(err)) => {
+                    eprintln!("nomouse: reading request: {err}");
+                    continue;
+                }
+                None => false,
+            };
+            if complete {
+                if let Err(err) = request.stream.set_nonblocking(false) {
+                    eprintln!("nomouse: {err}");
+                    continue;
+                }
+                handle_request(
+                    request.stream,
+                    &request.request,
+                    &mut active,
+                    &mut app,
+                    &mut event_queue,
+                    &mut font_system,
+                    &mut swash_cache,
+                );
+            } else if request.deadline <= now {
+                eprintln!("nomouse: the request didn't arrive in time");
+            } else {
+                pending.push(request);
+            }
+        }
+        while incoming && let Ok((stream, _)) = listener.accept() {
+            match PendingRequest::new(stream) {
+                Ok(request) => pending.push(request),
+                Err(err) => eprintln!("nomouse: {err}"),
+            }
+        }
+        if let Some((session, _)) = active.as_mut()
+            && let Some(Outcome { status, stdout }) = session.update(
+                &mut Wayland::new(&mut app, &mut event_queue),
+                &mut font_system,
+                &mut swash_cache,
+            )
+        {
+            let (session, stream) = active.take().unwrap();
+            session.end(&mut Wayland::new(&mut app, &mut event_queue));
+            respond(stream, status, &stdout, "");
+        }
+    }
+}
+
+/// Turns the signals that usually stop a program into events on a file
+/// descriptor, so the daemon cleans up before it exits.
+fn stop_signals() -> Result<SignalFd, Errno> {
+    let mut mask = SigSet::empty();
+    for signal in [Signal::SIGINT, Signal::SIGTERM, Signal::SIGHUP] {
+        mask.add(signal);
+    }
+    mask.thread_block()?;
+    SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC)
+}
+
+fn handle_request(
+    stream: UnixStream,
+    request: &[u8],
+    active: &mut Option<(Session, UnixStream)>,
+    app: &mut AppData,
+    event_queue: &mut EventQueue<AppData>,
+    font_system: &mut FontSystem,
+    swash_cache: &mut SwashCache,
+) {
+    let args = match Request::parse(&decode(request)) {
+        Ok(Request::Show(args)) => args,
+        Ok(Request::Cancel) => {
+            if let Some((session, _)) = active.as_mut() {
+                session.cancel();
+                respond(stream, 0, "", "");
+            } else {
+                respond(stream, EXIT_ERROR, "", "nomouse: nothing to cancel\n");
+            }
+            return;
+        }
+        Err(err) => {
+            respond(
+                stream,
+                EXIT_USAGE,
+                "",
+                &format!("nomouse: {err}\n\n{USAGE}"),
+            );
+            return;
+        }
+    };
+    if args.help {
+        respond(stream, 0, USAGE, "");
+        return;
+    }
+    if active.is_some() {
+        respond(
+            stream,
+            EXIT_ERROR,
+            "",
+            "nomouse: already showing the overlay\n",
+        );
+        return;
+    }
+    // Picked up again every time, so changes don't need a restart
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            respond(
+                stream,
+                EXIT_ERROR,
+                "",
+                &format!("nomouse: invalid config: {err}\n"),
+            );
+            return;
+        }
+    };
+    if args.repeat_last {
+        match session::repeat_last(&mut Wayland::new(app, event_queue), &config) {
+            Ok(Outcome { status, stdout }) => respond(stream, status, &stdout, ""),
+            Err(err) => respond(stream, EXIT_ERROR, "", &format!("nomouse: {err}\n")),
+        }
+        return;
+    }
+    let mut backend = Wayland::new(app, event_queue);
+    let mut session = match Session::start(&mut backend, args, config) {
+        Ok(session) => session,
+        Err(err) => {
+            respond(stream, EXIT_ERROR, "", &format!("nomouse: {err}\n"));
+            return;
+        }
+    };
+    // Draw right away instead of waiting for the next event
+    match session.update(&mut backend, font_system, swash_cache) {
+        Some(Outcome { status, stdout }) => {
+            session.end(&mut backend);
+            respond(stream, status, &stdout, "");
+        }
+        None => *active = Some((session, stream)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_fields() {
+        let fields = ["show", "--mode", "", "ünïcode"];
+        let bytes = encode(&fields);
+        assert_eq!(bytes, b"show\0--mode\0\0\xc3\xbcn\xc3\xafcode\0");
+        assert_eq!(decode(&bytes), fields);
+        assert_eq!(decode(&encode(&["1", "out\n", ""])), ["1", "out\n", ""]);
+        assert!(decode(b"").is_empty());
+        // A field that was cut off is dropped
+        assert_eq!(decode(b"0\0stdo"), ["0"]);
+    }
+
+    #[test]
+    fn parses_requests() {
+        let parse = |message: &[&str]| {
+            Request::parse(
+                &message
+                    .iter()
+                    .map(|arg| arg.to_string())
+                    .collect::<Vec<_>>(),
+            )
+        };
+        assert!(matches!(parse(&["cancel"]), Ok(Request::Cancel)));
+        assert!(matches!(parse(&["show"]), Ok(Request::Show(_))));
+        assert!(matches!(parse(&["show", "--help"]), Ok(Request::Show(args)) if args.help));
+        assert!(parse(&["cancel", "now"]).is_err());
+        assert!(parse(&["hide"]).is_err());
+        assert!(parse(&[]).is_err());
+    }
+
+    #[test]
+    fn binds_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nomouse.sock");
+        let listener = bind(&path).unwrap();
+        assert!(bind(&path).unwrap_err().contains("already running"));
+        // Left behind by a daemon that didn't exit cleanly
+        drop(listener);
+        let listener = bind(&path).unwrap();
+        drop(SocketFile(path.clone()));
+        assert!(!path.exists());
+        drop(listener);
+
+        fs::write(&path, "").unwrap();
+        assert!(bind(&path).unwrap_err().contains("isn't a socket"));
+        assert!(path.exists());
+    }
+}
@@ -0,0 +1,284 @@
+use cosmic_text::{FontSystem, SwashCache};
+use std::fs;
+
+use crate::{
+    cli::{EXIT_ERROR, PreviewArgs},
+    config::Config,
+    png,
+    position_selector::{InitialSelector, SelectorState},
+};
+
+/// Renders `selector` into a new premultiplied ARGB8888 buffer, as it would
+/// be shown on `output` if its buffer was `width` by `height` pixels at
+/// `scale`.
+#[allow(clippy::too_many_arguments)]
+pub fn render(
+    selector: &SelectorState,
+    output: u32,
+    width: usize,
+    height: usize,
+    scale: f64,
+    font_system: &mut FontSystem,
+    swash_cache: &mut SwashCache,
+    config: &Config,
+) -> Vec<u8> {
+    let mut buf = vec![0; width * height * 4];
+    selector.render(
+        output,
+        &mut buf,
+        width,
+        height,
+        scale,
+        font_system,
+        swash_cache,
+        config,
+    );
+    buf
+}
+
+/// The grid of a single output after typing `keys`, the way it looks before
+/// the first key when `keys` is empty.
+pub fn selector_after(keys: &str, width: usize, height: usize, config: &Config) -> SelectorState {
+    let mut selector = SelectorState::Initial(InitialSelector::new(
+        0,
+        &config.hints,
+        &config.grid,
+        width,
+        height,
+    ));
+    for key in keys.chars() {
+        selector.handle_key(key);
+    }
+    selector
+}
+
+/// Writes the overlay to a PNG file instead of showing it, for trying out a
+/// config without a compositor. Returns the exit status to exit with.
+pub fn preview(args: &PreviewArgs) -> i32 {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("nomouse: invalid config: {err}");
+            return EXIT_ERROR;
+        }
+    };
+    let width = (args.size.0 as f64 * args.scale).round() as usize;
+    let height = (args.size.1 as f64 * args.scale).round() as usize;
+    let selector = selector_after(&args.keys, width, height, &config);
+    let buf = render(
+        &selector,
+        0,
+        width,
+        height,
+        args.scale,
+        &mut FontSystem::new(),
+        &mut SwashCache::new(),
+        &config,
+    );
+    if let Err(err) = fs::write(&args.path, png::encode(&buf, width, height)) {
+        eprintln!("nomouse: {}: {err}", args.path.display());
+        return EXIT_ERROR;
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmic_text::fontdb;
+    use std::{env, path::PathBuf};
+
+    use super::*;
+    use crate::{hints::LabelStyle, position_selector::OutputSelector};
+
+    const WIDTH: usize = 640;
+    const HEIGHT: usize = 360;
+
+    /// Renders with the bundled font only, so the output doesn't depend on
+    /// the fonts installed on the machine running the tests.
+    fn fonts() -> (FontSystem, SwashCache, Config) {
+        let mut db = fontdb::Database::new();
+        db.load_font_data(include_bytes!("../tests/fonts/Tuffy.ttf").to_vec());
+        db.set_sans_serif_family("Tuffy");
+        let font_system = FontSystem::new_with_locale_and_db("en-US".into(), db);
+        (font_system, SwashCache::new(), Config::default())
+    }
+
+    /// Compares against `tests/golden/{name}.png`. Run the tests with
+    /// `NOMOUSE_UPDATE_GOLDEN=1` to write the current rendering there instead.
+    fn assert_golden(name: &str, selector: &SelectorState, output: u32) {
+        assert_golden_at(name, selector, output, (WIDTH, HEIGHT), 1.0);
+    }
+
+    fn assert_golden_at(
+        name: &str,
+        selector: &SelectorState,
+        output: u32,
+        (width, height): (usize, usize),
+        scale: f64,
+    ) {
+        let (mut font_system, mut swash_cache, config) = fonts();
+        let buf = render(
+            selector,
+            output,
+            width,
+            height,
+            scale,
+            &mut font_system,
+            &mut swash_cache,
+            &config,
+        );
+        let actual = png::encode(&buf, width, height);
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(format!("{name}.png"));
+        if env::var_os("NOMOUSE_UPDATE_GOLDEN").is_some() {
+            fs::write(&path, actual).unwrap();
+            return;
+        }
+        let expected = fs::read(&path).unwrap_or_else(|err| {
+            panic!(
+                "{}: {err}, run with NOMOUSE_UPDATE_GOLDEN=1",
+                path.display()
+            )
+        });
+        if let Some(difference) = difference(&actual, &expected) {
+            let actual_path = env::temp_dir().join(format!("nomouse-{name}.png"));
+            fs::write(&actual_path, actual).unwrap();
+            panic!(
+                "{name} doesn't match {}: {difference}, the rendering was written to {}",
+                path.display(),
+                actual_path.display()
+            );
+        }
+    }
+
+    /// How the pixels of two PNGs differ, ignoring how they were encoded.
+    fn difference(actual: &[u8], expected: &[u8]) -> Option<String> {
+        let (actual, width, height) = png::decode(actual).unwrap();
+        let (expected, expected_width, expected_height) = match png::decode(expected) {
+            Ok(image) => image,
+            Err(err) => return Some(format!("can't decode the golden image: {err}")),
+        };
+        if (width, height) != (expected_width, expected_height) {
+            return Some(format!(
+                "the size is {width}x{height} instead of {expected_width}x{expected_height}"
+            ));
+        }
+        let i = actual
+            .chunks_exact(4)
+            .zip(expected.chunks_exact(4))
+            .position(|(actual, expected)| actual != expected)?;
+        Some(format!(
+            "the first differing pixel is at ({}, {}), {:?} instead of {:?}",
+            i % width,
+            i / width,
+            &actual[i * 4..i * 4 + 4],
+            &expected[i * 4..i * 4 + 4]
+        ))
+    }
+
+    fn selector_after(keys: &str) -> SelectorState {
+        super::selector_after(keys, WIDTH, HEIGHT, &Config::default())
+    }
+
+    #[test]
+    fn initial_grid() {
+        assert_golden("initial", &selector_after(""), 0);
+    }
+
+    #[test]
+    fn initial_grid_after_one_key() {
+        assert_golden("initial_after_one_key", &selector_after("w"), 0);
+    }
+
+    #[test]
+    fn final_grid() {
+        let selector = selector_after("wa");
+        assert!(matches!(&selector, SelectorState::Final(selector) if selector.depth == 0));
+        assert_golden("final_depth_0", &selector, 0);
+    }
+
+    #[test]
+    fn final_grid_deeper() {
+        let selector = selector_after("was");
+        assert!(matches!(&selector, SelectorState::Final(selector) if selector.depth == 1));
+        assert_golden("final_depth_1", &selector, 0);
+        let selector = selector_after("wasz");
+        assert!(matches!(&selector, SelectorState::Final(selector) if selector.depth == 2));
+        assert_golden("final_depth_2", &selector, 0);
+    }
+
+    #[test]
+    fn single_cell_final_grid_is_skipped() {
+        let mut config = Config::default();
+        (config.grid.final_rows, config.grid.final_columns) = (1, 1);
+        let selector = super::selector_after("wa", WIDTH, HEIGHT, &config);
+        let SelectorState::Final(selector) = selector else {
+            panic!("{selector:?}");
+        };
+        // Selected right away, the cell the label was typed for
+        assert_eq!(selector.depth, 1);
+        assert_eq!((selector.width, selector.height), (WIDTH / 12, HEIGHT / 16));
+    }
+
+    #[test]
+    fn initial_grid_scaled() {
+        let size = (WIDTH * 3 / 2, HEIGHT * 3 / 2);
+        let selector = super::selector_after("", size.0, size.1, &Config::default());
+        assert_golden_at("initial_scale_1.5", &selector, 0, size, 1.5);
+    }
+
+    #[test]
+    fn portrait() {
+        let size = (HEIGHT, WIDTH);
+        let selector = super::selector_after("", size.0, size.1, &Config::default());
+        assert_golden_at("initial_portrait", &selector, 0, size, 1.0);
+        let selector = super::selector_after("wa", size.0, size.1, &Config::default());
+        assert_golden_at("final_portrait", &selector, 0, size, 1.0);
+    }
+
+    #[test]
+    fn shortest_labels() {
+        let mut config = Config::default();
+        config.hints.labels = LabelStyle::Shortest;
+        let selector = super::selector_after("", WIDTH, HEIGHT, &config);
+        assert_golden("initial_shortest", &selector, 0);
+        // One of the cells with a two key label
+        let selector = super::selector_after("q", WIDTH, HEIGHT, &config);
+        assert_golden("initial_shortest_after_one_key", &selector, 0);
+        let selector = super::selector_after("a", WIDTH, HEIGHT, &config);
+        assert!(matches!(selector, SelectorState::Final(_)));
+    }
+
+    #[test]
+    fn output_picker() {
+        let config = Config::default();
+        let grids = (0..2)
+            .map(|output| InitialSelector::new(output, &config.hints, &config.grid, WIDTH, HEIGHT))
+            .collect();
+        let mut selector =
+            SelectorState::Output(OutputSelector::new(&config.hints.alphabet, grids).unwrap());
+        assert_golden("output_picker", &selector, 1);
+        // The first output was picked, the second one is cleared
+        selector.handle_key('q');
+        assert_golden("output_picked_other", &selector, 1);
+    }
+
+    #[test]
+    fn more_outputs_than_keys() {
+        let config = Config::default();
+        let grids = |count| {
+            (0..count)
+                .map(|output| {
+                    InitialSelector::new(output, &config.hints, &config.grid, WIDTH, HEIGHT)
+                })
+                .collect()
+        };
+        let alphabet = &config.hints.alphabet[..3];
+        assert!(OutputSelector::new(alphabet, grids(3)).is_ok());
+        assert_eq!(
+            OutputSelector::new(alphabet, grids(4)).unwrap_err(),
+            "4 outputs but only 3 hint characters to pick one with"
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::VecDeque;
+
+/// How the cells of the first grid are labelled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LabelStyle {
+    /// Two keys per cell, the first one picks the column and the second one
+    /// the row
+    #[default]
+    Grid,
+    /// As few keys as the number of cells allows, see `shortest_labels`
+    Shortest,
+}
+
+impl LabelStyle {
+    pub const NAMES: &str = "grid or shortest";
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "grid" => Some(LabelStyle::Grid),
+            "shortest" => Some(LabelStyle::Shortest),
+            _ => None,
+        }
+    }
+}
+
+/// Labels for `count` targets that need the fewest keys in total when every
+/// target is as likely as the others. No label is the start of another one,
+/// so a target is picked as soon as its last key is typed. Labels are at
+/// most one key apart in length, the short ones come first.
+///
+/// Starts out with one key per label and keeps replacing the shortest label
+/// with every key appended to it, like vimium does, until there are enough.
+/// Needs a key for a single target and at least two for more.
+pub fn shortest_labels(count: usize, alphabet: &[char]) -> Vec<Vec<char>> {
+    assert!(
+        alphabet.len() >= count.min(2),
+        "can't tell {count} targets apart with {} keys",
+        alphabet.len()
+    );
+    let mut labels: VecDeque<Vec<char>> = alphabet.iter().map(|&key| vec![key]).collect();
+    while labels.len() < count {
+        let prefix = labels.pop_front().unwrap();
+        for &key in alphabet {
+            let mut label = prefix.clone();
+            label.push(key);
+            labels.push_back(label);
+        }
+    }
+    // The last label that was replaced has at least two of its extensions
+    // left after this, so none of them could have been shorter
+    labels.truncate(count);
+    labels.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortest_labels_are_prefix_free_and_short() {
+        let alphabet: Vec<char> = "qweruiopasdfjkl;zxcvm,./".chars().collect();
+        for count in [0, 1, 5, 24, 25, 47, 48, 192, 600] {
+            let labels = shortest_labels(count, &alphabet);
+            assert_eq!(labels.len(), count);
+            for (i, label) in labels.iter().enumerate() {
+                for (j, other) in labels.iter().enumerate() {
+                    assert!(i == j || !other.starts_with(label), "{label:?} {other:?}");
+                }
+            }
+        }
+        let labels = shortest_labels(192, &alphabet);
+        let single = labels.iter().filter(|label| label.len() == 1).count();
+        // Every replaced label turns one key into 24 two key labels
+        assert_eq!(single, 16);
+        assert!(labels.iter().all(|label| label.len() <= 2));
+    }
+}
@@ -0,0 +1,323 @@
+use std::{fmt, os::fd::OwnedFd};
+
+use xkbcommon::xkb;
+
+/// Translates evdev keycodes from `wl_keyboard` into characters using the
+/// keymap the compositor sent us, so labels match the user's layout.
+pub struct Keyboard {
+    context: xkb::Context,
+    state: Option<xkb::State>,
+}
+
+impl Keyboard {
+    /// With the keymap xkb compiles from the RMLVO names, like the X server
+    /// does for its keyboard.
+    pub fn from_names(
+        rules: &str,
+        model: &str,
+        layout: &str,
+        variant: &str,
+        options: Option<String>,
+    ) -> Option<Self> {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb::Keymap::new_from_names(
+            &context,
+            rules,
+            model,
+            layout,
+            variant,
+            options,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )?;
+        Some(Self {
+            state: Some(xkb::State::new(&keymap)),
+            context,
+        })
+    }
+
+    pub fn set_keymap(&mut self, fd: OwnedFd, size: u32) {
+        let keymap = unsafe {
+            xkb::Keymap::new_from_fd(
+                &self.context,
+                fd,
+                size as usize,
+                xkb::KEYMAP_FORMAT_TEXT_V1,
+                xkb::KEYMAP_COMPILE_NO_FLAGS,
+            )
+        };
+        match keymap {
+            Ok(Some(keymap)) => self.state = Some(xkb::State::new(&keymap)),
+            Ok(None) => eprintln!("Compositor sent a keymap that could not be compiled"),
+            Err(err) => eprintln!("Could not map keymap: {err}"),
+        }
+    }
+
+    pub fn update_modifiers(&mut self, depressed: u32, latched: u32, locked: u32, group: u32) {
+        if let Some(state) = self.state.as_mut() {
+            state.update_mask(depressed, latched, locked, 0, 0, group);
+        }
+    }
+
+    /// Presses or releases the evdev keycode `key`, for display servers that
+    /// don't send the modifiers it changes.
+    pub fn update_key(&mut self, key: u32, pressed: bool) {
+        if let Some(state) = self.state.as_mut() {
+            let direction = if pressed {
+                xkb::KeyDirection::Down
+            } else {
+                xkb::KeyDirection::Up
+            };
+            state.update_key(xkb::Keycode::new(key + 8), direction);
+        }
+    }
+
+    /// The evdev keycode of the first key with `keysym` on its first level.
+    pub fn code_of(&self, keysym: xkb::Keysym) -> Option<u32> {
+        let keymap = self.state.as_ref()?.get_keymap();
+        (keymap.min_keycode().raw()..=keymap.max_keycode().raw())
+            .find(|&keycode| {
+                keymap
+                    .key_get_syms_by_level(xkb::Keycode::new(keycode), 0, 0)
+                    .contains(&keysym)
+            })
+            .map(|keycode| keycode - 8)
+    }
+
+    pub fn keysym(&self, key: u32) -> Option<xkb::Keysym> {
+        let state = self.state.as_ref()?;
+        // xkb keycodes are offset by 8 from the evdev ones wl_keyboard sends
+        Some(state.key_get_one_sym(xkb::Keycode::new(key + 8)))
+    }
+
+    /// The character on the first level of the key in the active layout, if
+    /// it is a printable one. Modifiers are ignored so labels can still be
+    /// typed while one is held to pick the action.
+    pub fn key_char(&self, key: u32) -> Option<char> {
+        let state = self.state.as_ref()?;
+        let keycode = xkb::Keycode::new(key + 8);
+        let layout = state.key_get_layout(keycode);
+        let keymap = state.get_keymap();
+        let keysym = *keymap.key_get_syms_by_level(keycode, layout, 0).first()?;
+        char::from_u32(xkb::keysym_to_utf32(keysym)).filter(|c| !c.is_control())
+    }
+
+    /// The character the key types with the modifiers that are held, e.g.
+    /// `M` for shift+m.
+    pub fn typed_char(&self, key: u32) -> Option<char> {
+        let state = self.state.as_ref()?;
+        let typed = state.key_get_utf8(xkb::Keycode::new(key + 8));
+        let mut chars = typed.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if !c.is_control() => Some(c),
+            _ => None,
+        }
+    }
+
+    /// Translates the press or release of the evdev keycode `code`.
+    pub fn key(&self, code: u32, pressed: bool) -> Key {
+        Key {
+            code,
+            pressed,
+            keysym: self.keysym(code),
+            key_char: self.key_char(code),
+            typed: self.typed_char(code),
+            modifiers: self.modifiers(),
+        }
+    }
+
+    /// The keymap the compositor sent, in the text format, for a virtual
+    /// keyboard whose modifiers should mean the same.
+    pub fn keymap_string(&self) -> Option<String> {
+        let state = self.state.as_ref()?;
+        Some(state.get_keymap().get_as_string(xkb::KEYMAP_FORMAT_TEXT_V1))
+    }
+
+    /// `modifiers` as a mask of the keymap the compositor sent, e.g. for
+    /// `zwp_virtual_keyboard_v1.modifiers`.
+    pub fn modifier_mask(&self, modifiers: Modifiers) -> u32 {
+        let Some(state) = self.state.as_ref() else {
+            return 0;
+        };
+        let keymap = state.get_keymap();
+        modifiers
+            .names()
+            .into_iter()
+            .map(|name| keymap.mod_get_index(name))
+            .filter(|&index| index != xkb::MOD_INVALID)
+            .fold(0, |mask, index| mask | 1 << index)
+    }
+
+    /// The locked modifiers and the active layout, to keep caps lock and
+    /// the layout as they are on a virtual keyboard.
+    pub fn locked(&self) -> (u32, u32) {
+        let Some(state) = self.state.as_ref() else {
+            return (0, 0);
+        };
+        (
+            state.serialize_mods(xkb::STATE_MODS_LOCKED),
+            state.serialize_layout(xkb::STATE_LAYOUT_EFFECTIVE),
+        )
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        let Some(state) = self.state.as_ref() else {
+            return Modifiers::default();
+        };
+        let active = |name| state.mod_name_is_active(name, xkb::STATE_MODS_EFFECTIVE);
+        Modifiers {
+            shift: active(xkb::MOD_NAME_SHIFT),
+            ctrl: active(xkb::MOD_NAME_CTRL),
+            alt: active(xkb::MOD_NAME_ALT),
+            logo: active(xkb::MOD_NAME_LOGO),
+        }
+    }
+}
+
+/// What the keyboard sent while the overlay had the focus, queued until the
+/// session gets to it.
+#[derive(Debug, Clone, Copy)]
+pub enum KeyboardEvent {
+    Key(Key),
+    /// The modifiers that are held changed
+    Modifiers(Modifiers),
+    /// The focus went elsewhere, keys held until then won't be released
+    Leave,
+}
+
+/// A key pressed or released, translated with the keymap.
+#[derive(Debug, Clone, Copy)]
+pub struct Key {
+    /// The evdev keycode, to tell which press a release belongs to
+    pub code: u32,
+    pub pressed: bool,
+    pub keysym: Option<xkb::Keysym>,
+    /// See `Keyboard::key_char`
+    pub key_char: Option<char>,
+    /// See `Keyboard::typed_char`
+    pub typed: Option<char>,
+    /// The modifiers held at the time
+    pub modifiers: Modifiers,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub logo: bool,
+}
+
+impl Modifiers {
+    /// The xkb names of the modifiers that are set.
+    pub fn names(self) -> Vec<&'static str> {
+        [
+            (self.shift, xkb::MOD_NAME_SHIFT),
+            (self.ctrl, xkb::MOD_NAME_CTRL),
+            (self.alt, xkb::MOD_NAME_ALT),
+            (self.logo, xkb::MOD_NAME_LOGO),
+        ]
+        .into_iter()
+        .filter_map(|(set, name)| set.then_some(name))
+        .collect()
+    }
+
+    pub fn is_empty(self) -> bool {
+        self == Self::default()
+    }
+
+    /// The modifier `keysym` is one of the keys of, if any.
+    pub fn of_key(keysym: xkb::Keysym) -> Option<Self> {
+        use xkb::Keysym;
+        let mut modifiers = Self::default();
+        match keysym {
+            Keysym::Shift_L | Keysym::Shift_R => modifiers.shift = true,
+            Keysym::Control_L | Keysym::Control_R => modifiers.ctrl = true,
+            Keysym::Alt_L | Keysym::Alt_R | Keysym::Meta_L | Keysym::Meta_R => modifiers.alt = true,
+            Keysym::Super_L | Keysym::Super_R => modifiers.logo = true,
+            _ => return None,
+        }
+        Some(modifiers)
+    }
+
+    /// Flips the modifiers that are set in `other`.
+    pub fn toggle(&mut self, other: Self) {
+        self.shift ^= other.shift;
+        self.ctrl ^= other.ctrl;
+        self.alt ^= other.alt;
+        self.logo ^= other.logo;
+    }
+}
+
+impl fmt::Display for Modifiers {
+    /// Like `ctrl+shift`, in the order they are usually written in.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (self.ctrl, "ctrl"),
+            (self.alt, "alt"),
+            (self.shift, "shift"),
+            (self.logo, "super"),
+        ];
+        let names: Vec<&str> = names
+            .into_iter()
+            .filter_map(|(set, name)| set.then_some(name))
+            .collect();
+        write!(f, "{}", names.join("+"))
+    }
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self {
+            context: xkb::Context::new(xkb::CONTEXT_NO_FLAGS),
+            state: None,
+        }
+    }
+}
+
+impl fmt::Debug for Keyboard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keyboard")
+            .field("has_keymap", &self.state.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+impl Keyboard {
+    /// With the xkb `layout` from the system's keyboard layouts, instead of
+    /// one sent by the compositor.
+    pub fn with_layout(layout: &str) -> Self {
+        Self::from_names("evdev", "pc105", layout, "", None).expect("layout compiles")
+    }
+
+    /// Holds down `modifiers` and nothing else.
+    pub fn hold(&mut self, modifiers: Modifiers) {
+        let mask = self.modifier_mask(modifiers);
+        self.update_modifiers(mask, 0, 0, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Evdev keycodes of the keys right of `m` on a US layout.
+    const KEY_COMMA: u32 = 51;
+    const KEY_DOT: u32 = 52;
+
+    #[test]
+    fn azerty_keys() {
+        let mut keyboard = Keyboard::with_layout("fr");
+        assert_eq!(keyboard.key_char(KEY_COMMA), Some(';'));
+        assert_eq!(keyboard.key_char(KEY_DOT), Some(':'));
+        keyboard.hold(Modifiers {
+            shift: true,
+            ..Default::default()
+        });
+        // Only typed with shift, the first level doesn't change
+        assert_eq!(keyboard.key_char(KEY_COMMA), Some(';'));
+        assert_eq!(keyboard.typed_char(KEY_COMMA), Some('.'));
+        assert_eq!(keyboard.typed_char(KEY_DOT), Some('/'));
+        assert!(keyboard.modifiers().shift);
+    }
+}
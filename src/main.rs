@@ -1,150 +1,143 @@
-use cosmic_text::{Attrs, Buffer, Color, Family, FontSystem, Metrics, Shaping, SwashCache};
-use nanoid::nanoid;
-use smithay_client_toolkit::seat::pointer::BTN_LEFT;
-use std::{
-    collections::HashMap,
-    i32,
-    os::{fd::AsFd, raw::c_void, unix::prelude::OwnedFd},
-    ptr::NonNull,
-};
-
+use cosmic_text::{FontSystem, SwashCache};
 use nix::{
-    fcntl::OFlag,
-    sys::{
-        mman::{ProtFlags, shm_open, shm_unlink},
-        stat::Mode,
-    },
-    unistd::ftruncate,
-};
-use wayland_client::{
-    EventQueue,
-    protocol::{wl_pointer::ButtonState, wl_shm::Format},
+    errno::Errno,
+    poll::{PollFd, PollFlags, PollTimeout, poll},
 };
+use std::{io, process};
+
+mod actions;
 mod app;
+mod backend;
 mod buf_utils;
+mod cli;
+mod config;
+mod daemon;
+mod headless;
+mod hints;
+mod keyboard_utils;
+mod normal_mode;
 mod output_utils;
+mod png;
+mod pointer;
 mod position_selector;
 mod render_utils;
-use app::AppData;
+mod selection;
+mod session;
+mod state;
+mod virtual_keyboard;
+mod x11;
+use app::{AppData, Wayland};
+use wayland_client::{EventQueue, backend::WaylandError};
 
-use crate::position_selector::{InitialSelector, SelectorState};
+use crate::{
+    cli::{Args, Command, EXIT_ERROR, EXIT_USAGE, USAGE},
+    config::Config,
+    session::Session,
+};
 
 fn main() {
-    // qwer uiop
-    // asdf jkl;
-    // zxcv m,./
-    let keycodes: Vec<u32> = vec![
-        16, 17, 18, 19, 22, 23, 24, 25, 30, 31, 32, 33, 36, 37, 38, 39, 44, 45, 46, 47, 50, 51, 52,
-        53,
-    ];
-    let keycode_symbols: HashMap<u32, String> = HashMap::from([
-        (16, "q".into()),
-        (17, "w".into()),
-        (18, "e".into()),
-        (19, "r".into()),
-        (22, "u".into()),
-        (23, "i".into()),
-        (24, "o".into()),
-        (25, "p".into()),
-        (30, "a".into()),
-        (31, "s".into()),
-        (32, "d".into()),
-        (33, "f".into()),
-        (36, "j".into()),
-        (37, "k".into()),
-        (38, "l".into()),
-        (39, ";".into()),
-        (44, "z".into()),
-        (45, "x".into()),
-        (46, "c".into()),
-        (47, "v".into()),
-        (50, "m".into()),
-        (51, ",".into()),
-        (52, ".".into()),
-        (53, "/".into()),
-    ]);
-    let conn = wayland_client::Connection::connect_to_env().unwrap();
-    let display = conn.display();
-    let mut event_queue: EventQueue<AppData> = conn.new_event_queue();
-    let qh = event_queue.handle();
-    let _registry = display.get_registry(&qh, ());
-    let mut app = AppData {
-        ..Default::default()
+    let command = match Command::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("nomouse: {err}\n\n{USAGE}");
+            process::exit(EXIT_USAGE);
+        }
     };
-    app.init_that_shit(&mut event_queue);
-    app.selector = Some(SelectorState::Initial(InitialSelector::new(
-        keycodes, 12, 16, 1920, 1080,
-    )));
-    let mut font_system = FontSystem::new();
-    let mut swash_cache = SwashCache::new();
-    let metrics = Metrics::new(28.0, 30.0);
-    let mut buffer = Buffer::new(&mut font_system, metrics);
-    let mut buffer = buffer.borrow_with(&mut font_system);
-    buffer.set_size(Some(80.0), Some(25.0));
-    let attrs = Attrs::new().family(Family::Serif);
-    buffer.set_text("hi", &attrs, Shaping::Advanced);
-    buffer.shape_until_scroll(true);
-    let text_color = Color::rgb(0x0, 0x0, 0x0);
+    match command {
+        Command::Run(args) => run(args),
+        Command::Daemon => process::exit(daemon::run()),
+        Command::Msg(message) => process::exit(daemon::send(&message)),
+        Command::Preview(args) => process::exit(headless::preview(&args)),
+    }
+}
 
-    let mut offset: u8 = 0;
-    loop {
-        println!("rendering");
-        offset = offset.wrapping_add(1);
-        let surface = app.surface.as_mut().unwrap();
-        let framebuf = surface.buf.as_mut_slice();
-        for i in 0..framebuf.len() / 4 {
-            // grey background
-            framebuf[i * 4] = 128_u8.wrapping_add(1);
-            framebuf[i * 4 + 1] = 128;
-            framebuf[i * 4 + 2] = 128;
-            framebuf[i * 4 + 3] = 128;
+/// Shows the overlay once and exits when the selection is done.
+fn run(args: Args) {
+    if args.help {
+        print!("{USAGE}");
+        return;
+    }
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("nomouse: invalid config: {err}");
+            process::exit(EXIT_ERROR);
+        }
+    };
+    if x11::is_x11_session() {
+        match x11::run(args, config) {
+            Ok(outcome) => {
+                print!("{}", outcome.stdout);
+                process::exit(outcome.status);
+            }
+            Err(err) => {
+                eprintln!("nomouse: {err}");
+                process::exit(EXIT_ERROR);
+            }
         }
+    }
+    let (mut app, mut event_queue) = match AppData::connect() {
+        Ok(connected) => connected,
+        Err(err) => {
+            eprintln!("nomouse: {err}");
+            process::exit(EXIT_ERROR);
+        }
+    };
+    if args.repeat_last {
+        match session::repeat_last(&mut Wayland::new(&mut app, &mut event_queue), &config) {
+            Ok(outcome) => process::exit(outcome.status),
+            Err(err) => {
+                eprintln!("nomouse: {err}");
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
+    let mut font_system = FontSystem::new();
+    let mut swash_cache = SwashCache::new();
+    let mut session =
+        match Session::start(&mut Wayland::new(&mut app, &mut event_queue), args, config) {
+            Ok(session) => session,
+            Err(err) => {
+                eprintln!("nomouse: {err}");
+                process::exit(EXIT_ERROR);
+            }
+        };
 
-        let monitor = app
-            .outputs
-            .get(app.selected_output.as_ref().unwrap())
-            .unwrap();
-        let screen_width = monitor.width.unwrap() as usize;
-        app.selector.as_ref().unwrap().draw(
-            framebuf,
-            screen_width,
+    let outcome = loop {
+        if let Some(outcome) = session.update(
+            &mut Wayland::new(&mut app, &mut event_queue),
             &mut font_system,
             &mut swash_cache,
-            &keycode_symbols,
+        ) {
+            break outcome;
+        }
+        dispatch(
+            &mut app,
+            &mut event_queue,
+            session::poll_timeout(session.next_update()),
         );
+    };
+    print!("{}", outcome.stdout);
+    process::exit(outcome.status);
+}
 
-        if let SelectorState::Final(selector) = app.selector.as_ref().unwrap() {
-            if selector.depth == 1 {
-                app.layer_surface.as_ref().unwrap().destroy();
-                app.layer_shell.as_ref().unwrap().destroy();
-                surface.wl_surface.destroy();
-                let monitor_x = monitor.x.unwrap();
-                let monitor_y = monitor.y.unwrap();
-                let width = monitor.width.unwrap();
-                let height = monitor.height.unwrap();
-
-                app.pointer.as_ref().unwrap().motion_absolute(
-                    0,
-                    monitor_x as u32 + selector.x as u32 + (selector.width / 2) as u32,
-                    monitor_y as u32 + selector.y as u32 + (selector.height / 2) as u32,
-                    width as u32,
-                    height as u32,
-                );
-                app.pointer
-                    .as_ref()
-                    .unwrap()
-                    .button(1, BTN_LEFT, ButtonState::Pressed);
-                app.pointer
-                    .as_ref()
-                    .unwrap()
-                    .button(2, BTN_LEFT, ButtonState::Released);
-                event_queue.blocking_dispatch(&mut app).unwrap();
-                break;
-            }
+/// Like `EventQueue::blocking_dispatch`, but stops waiting for events once
+/// `timeout` is up.
+fn dispatch(app: &mut AppData, event_queue: &mut EventQueue<AppData>, timeout: PollTimeout) {
+    event_queue.flush().unwrap();
+    if let Some(guard) = event_queue.prepare_read() {
+        let mut fds = [PollFd::new(guard.connection_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, timeout) {
+            // Timed out, dropping the guard cancels the read
+            Ok(0) => return,
+            Ok(_) | Err(Errno::EINTR) => {}
+            Err(err) => panic!("poll: {err}"),
+        }
+        match guard.read() {
+            Ok(_) => {}
+            Err(WaylandError::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => {}
+            Err(err) => panic!("wayland connection: {err}"),
         }
-        surface.wl_surface.attach(surface.wl_buf.as_ref(), 0, 0);
-        surface.wl_surface.damage(0, 0, i32::MAX, i32::MAX);
-        surface.wl_surface.commit();
-        event_queue.blocking_dispatch(&mut app).unwrap();
     }
+    event_queue.dispatch_pending(app).unwrap();
 }
@@ -0,0 +1,150 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    config::NormalConfig,
+    pointer::{Extents, PointerBackend},
+};
+
+/// Key repeat rate in keys per second and delay in milliseconds, for
+/// compositors that don't send `wl_keyboard.repeat_info`.
+pub const DEFAULT_REPEAT_INFO: (i32, i32) = (25, 600);
+
+/// Fine-tuning after a selection: the pointer moves while the direction keys
+/// are held, one step on the press and then at the key repeat rate, with the
+/// steps growing the longer the keys are held.
+#[derive(Debug)]
+pub struct NormalMode {
+    config: NormalConfig,
+    /// `None` when keys shouldn't repeat
+    repeat_interval: Option<Duration>,
+    repeat_delay: Duration,
+    /// Evdev keycodes of the held direction keys, with their direction and
+    /// when they were pressed
+    held: Vec<(u32, (i32, i32), Instant)>,
+    next_step: Option<Instant>,
+    /// Set while shift is held, for steps of `config.slow_step`
+    pub slow: bool,
+    /// In the global compositor space, kept track of here since relative
+    /// motion doesn't tell where the pointer ends up
+    position: (f64, f64),
+    /// The layout the pointer is kept in, see `layout_extents`
+    extents: Extents,
+}
+
+impl NormalMode {
+    pub fn new(
+        config: NormalConfig,
+        (rate, delay): (i32, i32),
+        (x, y): (i32, i32),
+        extents: Extents,
+    ) -> Self {
+        Self {
+            config,
+            repeat_interval: (rate > 0).then(|| Duration::from_secs(1) / rate as u32),
+            repeat_delay: Duration::from_millis(delay.max(0) as u64),
+            held: Vec::new(),
+            next_step: None,
+            slow: false,
+            position: (x as f64, y as f64),
+            extents,
+        }
+    }
+
+    /// Where the pointer is in the global compositor space.
+    pub fn position(&self) -> (i32, i32) {
+        (
+            self.position.0.round() as i32,
+            self.position.1.round() as i32,
+        )
+    }
+
+    /// The direction `key` moves the pointer in, if it is one of the
+    /// direction keys.
+    pub fn direction_for(&self, key: char) -> Option<(i32, i32)> {
+        let config = &self.config;
+        match key {
+            _ if key == config.left => Some((-1, 0)),
+            _ if key == config.right => Some((1, 0)),
+            _ if key == config.up => Some((0, -1)),
+            _ if key == config.down => Some((0, 1)),
+            _ => None,
+        }
+    }
+
+    pub fn press(
+        &mut self,
+        keycode: u32,
+        direction: (i32, i32),
+        now: Instant,
+        pointer: &mut dyn PointerBackend,
+    ) {
+        self.held.retain(|(held, _, _)| *held != keycode);
+        self.held.push((keycode, direction, now));
+        self.step(now, pointer);
+        if self.repeat_interval.is_some() {
+            self.next_step = Some(now + self.repeat_delay);
+        }
+    }
+
+    pub fn release(&mut self, keycode: u32) {
+        self.held.retain(|(held, _, _)| *held != keycode);
+        if self.held.is_empty() {
+            self.next_step = None;
+        }
+    }
+
+    pub fn release_all(&mut self) {
+        self.held.clear();
+        self.next_step = None;
+    }
+
+    /// When `tick` has the next step to take.
+    pub fn next_step(&self) -> Option<Instant> {
+        self.next_step
+    }
+
+    /// Takes the steps that are due by `now`.
+    pub fn tick(&mut self, now: Instant, pointer: &mut dyn PointerBackend) {
+        let (Some(mut next), Some(interval)) = (self.next_step, self.repeat_interval) else {
+            return;
+        };
+        if next > now {
+            return;
+        }
+        self.step(now, pointer);
+        next += interval;
+        // Don't make up for steps missed while busy, that would jump
+        self.next_step = Some(next.max(now + interval / 2));
+    }
+
+    fn step(&mut self, now: Instant, pointer: &mut dyn PointerBackend) {
+        let (mut dx, mut dy) = (0, 0);
+        for (_, (x, y), _) in &self.held {
+            dx += x;
+            dy += y;
+        }
+        let (dx, dy) = (dx.signum() as f64, dy.signum() as f64);
+        if dx == 0.0 && dy == 0.0 {
+            return;
+        }
+        let distance = if self.slow {
+            self.config.slow_step
+        } else {
+            let first_press = self.held.iter().map(|(_, _, pressed)| *pressed).min();
+            let repeating = first_press
+                .map(|pressed| now.saturating_duration_since(pressed + self.repeat_delay))
+                .unwrap_or_default();
+            let step =
+                self.config.step * (1.0 + self.config.acceleration * repeating.as_secs_f64());
+            step.min(self.config.max_step)
+        };
+
+        let (left, top, width, height) = self.extents;
+        let (x, y) = self.position;
+        let new_x = (x + dx * distance).clamp(left as f64, (left + width as i32 - 1) as f64);
+        let new_y = (y + dy * distance).clamp(top as f64, (top + height as i32 - 1) as f64);
+        self.position = (new_x, new_y);
+        pointer.motion(new_x - x, new_y - y);
+        pointer.frame();
+    }
+}
@@ -1,10 +1,10 @@
 use wayland_client::{
     WEnum,
-    protocol::{
-        wl_buffer::WlBuffer,
-        wl_output::{Mode, Subpixel, Transform, WlOutput},
-    },
+    protocol::wl_output::{Mode, Subpixel, Transform, WlOutput},
 };
+use wayland_protocols::xdg::xdg_output::zv1::client::zxdg_output_v1::ZxdgOutputV1;
+
+use crate::pointer::Extents;
 
 //wayland_client::protocol::wl_output::Event::Geometry {
 //    x,
@@ -26,9 +26,41 @@ use wayland_client::{
 //wayland_client::protocol::wl_output::Event::Scale { factor } => todo!(),
 //wayland_client::protocol::wl_output::Event::Name { name } => todo!(),
 //wayland_client::protocol::wl_output::Event::Description { description } => todo!(),
+/// Stands in for the output of an overlay until the compositor has told us
+/// which output it picked. Registry names start at 1, so this never names an
+/// actual output.
+pub const UNKNOWN_OUTPUT: u32 = 0;
+
+/// Which outputs the overlay is shown on.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum OutputTarget {
+    /// The one the compositor considers focused
+    #[default]
+    Focused,
+    All,
+    /// The one the pointer is on
+    Pointer,
+    /// The one with this name or description
+    Name(String),
+}
+
+impl OutputTarget {
+    pub fn parse(target: &str) -> Self {
+        match target {
+            "focused" => OutputTarget::Focused,
+            "all" => OutputTarget::All,
+            "pointer" => OutputTarget::Pointer,
+            name => OutputTarget::Name(name.to_string()),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Output {
     pub wl_output: WlOutput,
+    /// For the logical size, when the compositor supports
+    /// `zxdg_output_manager_v1`
+    pub xdg_output: Option<ZxdgOutputV1>,
     pub x: Option<i32>,
     pub y: Option<i32>,
     pub physical_width: Option<i32>,
@@ -44,12 +76,18 @@ pub struct Output {
     pub scale: Option<i32>,
     pub name: Option<String>,
     pub description: Option<String>,
+    /// Size in the global compositor space, sent by `xdg_output` or known
+    /// once an overlay has been configured on the output
+    pub logical_width: Option<i32>,
+    pub logical_height: Option<i32>,
+    pub done: bool,
 }
 
 impl Output {
     pub fn new(wl_output: WlOutput) -> Self {
         Self {
             wl_output,
+            xdg_output: None,
             x: None,
             y: None,
             physical_width: None,
@@ -65,6 +103,87 @@ impl Output {
             scale: None,
             name: None,
             description: None,
+            logical_width: None,
+            logical_height: None,
+            done: false,
+        }
+    }
+
+    /// Whether `wanted` is the name (e.g. `DP-1`) or description of the output.
+    pub fn matches(&self, wanted: &str) -> bool {
+        self.name.as_deref() == Some(wanted) || self.description.as_deref() == Some(wanted)
+    }
+
+    /// The size in the global compositor space. Without `xdg_output` this
+    /// falls back to the mode turned by the transform and divided by the
+    /// integer scale until an overlay has been configured, which is off for
+    /// fractional scales.
+    pub fn logical_size(&self) -> (i32, i32) {
+        if let (Some(width), Some(height)) = (self.logical_width, self.logical_height) {
+            return (width, height);
+        }
+        let scale = self.scale.unwrap_or(1).max(1);
+        let (width, height) = (
+            self.width.unwrap_or(0) / scale,
+            self.height.unwrap_or(0) / scale,
+        );
+        if self.is_rotated() {
+            (height, width)
+        } else {
+            (width, height)
         }
     }
+
+    /// The position and size in the global compositor space.
+    pub fn rect(&self) -> (i32, i32, i32, i32) {
+        let (width, height) = self.logical_size();
+        (self.x.unwrap_or(0), self.y.unwrap_or(0), width, height)
+    }
+
+    /// Whether the output is turned on its side, so the mode is taller than
+    /// it is wide or the other way around in the compositor space.
+    pub fn is_rotated(&self) -> bool {
+        matches!(
+            self.transform,
+            Some(WEnum::Value(
+                Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270
+            ))
+        )
+    }
+}
+
+/// The bounding box of the `(x, y, width, height)` rects of all outputs in
+/// the global compositor space. `motion_absolute` positions are relative to
+/// it. Empty at the origin without any outputs.
+pub fn layout_extents(rects: impl IntoIterator<Item = (i32, i32, i32, i32)>) -> Extents {
+    let mut rects = rects.into_iter();
+    let Some((x, y, width, height)) = rects.next() else {
+        return (0, 0, 0, 0);
+    };
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (x, y, x + width, y + height);
+    for (x, y, width, height) in rects {
+        min_x = min_x.min(x);
+        min_y = min_y.min(y);
+        max_x = max_x.max(x + width);
+        max_y = max_y.max(y + height);
+    }
+    (min_x, min_y, (max_x - min_x) as u32, (max_y - min_y) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::layout_extents;
+
+    #[test]
+    fn extents_of_no_outputs() {
+        assert_eq!(layout_extents([]), (0, 0, 0, 0));
+    }
+
+    #[test]
+    fn extents_of_outputs() {
+        assert_eq!(layout_extents([(0, 0, 1920, 1080)]), (0, 0, 1920, 1080));
+        // A portrait output left of and above the primary one
+        let rects = [(0, 0, 2560, 1440), (-1080, -400, 1080, 1920)];
+        assert_eq!(layout_extents(rects), (-1080, -400, 3640, 1920));
+    }
 }
@@ -0,0 +1,54 @@
+// Writes out a framebuffer as a PNG, so rendering can be looked at and
+// compared without a compositor.
+
+/// Encodes a premultiplied ARGB8888 framebuffer, as handed to the
+/// compositor, as an 8 bit RGBA PNG.
+pub fn encode(buf: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for pixel in buf[..width * height * 4].chunks_exact(4) {
+        let [b, g, r, a] = [pixel[0], pixel[1], pixel[2], pixel[3]];
+        let unpremultiply = |c: u8| match a {
+            0 => 0,
+            a => ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8,
+        };
+        rgba.extend_from_slice(&[unpremultiply(r), unpremultiply(g), unpremultiply(b), a]);
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = ::png::Encoder::new(&mut png, width as u32, height as u32);
+    encoder.set_color(::png::ColorType::Rgba);
+    encoder.set_depth(::png::BitDepth::Eight);
+    // Writing to a Vec only fails on sizes PNG can't hold
+    let mut writer = encoder.write_header().expect("PNG header");
+    writer.write_image_data(&rgba).expect("PNG image data");
+    writer.finish().expect("PNG end");
+    png
+}
+
+/// Decodes a PNG to 8 bit RGBA, with its width and height.
+#[cfg(test)]
+pub fn decode(png: &[u8]) -> Result<(Vec<u8>, usize, usize), ::png::DecodingError> {
+    let mut decoder = ::png::Decoder::new(png);
+    decoder.set_transformations(::png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let (color_type, _) = reader.output_color_type();
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    buf.truncate(info.buffer_size());
+    let rgba = match color_type {
+        ::png::ColorType::Rgba => buf,
+        ::png::ColorType::Rgb => buf
+            .chunks_exact(3)
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+            .collect(),
+        ::png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .flat_map(|pixel| [pixel[0], pixel[0], pixel[0], pixel[1]])
+            .collect(),
+        _ => buf
+            .iter()
+            .flat_map(|&gray| [gray, gray, gray, 255])
+            .collect(),
+    };
+    Ok((rgba, info.width as usize, info.height as usize))
+}
@@ -0,0 +1,163 @@
+use std::fmt;
+
+use wayland_client::{
+    QueueHandle,
+    protocol::wl_pointer::{Axis, AxisSource, ButtonState},
+};
+use wayland_protocols_wlr::virtual_pointer::v1::client::{
+    zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1,
+    zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1,
+};
+
+use crate::{actions::timestamp, app::AppData};
+
+mod ei;
+mod portal;
+mod uinput;
+
+/// The extents of the output layout in the global compositor space, see
+/// `layout_extents`.
+pub type Extents = (i32, i32, u32, u32);
+
+/// Moves the pointer and presses its buttons, through whichever way of
+/// faking input the compositor or the kernel offers.
+pub trait PointerBackend: fmt::Debug {
+    /// Moves the pointer to `position` in the global compositor space, which
+    /// lies within `extents`.
+    fn motion_absolute(&mut self, position: (i32, i32), extents: Extents);
+
+    /// Moves the pointer by `dx` and `dy` logical pixels.
+    fn motion(&mut self, dx: f64, dy: f64);
+
+    /// Presses or releases an evdev button like `BTN_LEFT`.
+    fn button(&mut self, button: u32, state: ButtonState);
+
+    /// Scrolls one wheel step in `direction` (1 or -1) along `axis`, `step`
+    /// being the distance of one step for backends that need one.
+    fn scroll(&mut self, axis: Axis, direction: i32, step: f64);
+
+    /// Ends a group of events that happen at the same time, like
+    /// `wl_pointer.frame`.
+    fn frame(&mut self);
+}
+
+/// Which `PointerBackend` to use, `backend` in the config.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    /// The first one that works, in the order below
+    #[default]
+    Auto,
+    /// `zwlr_virtual_pointer_v1`, wlroots based compositors
+    Wlr,
+    /// libei through `$LIBEI_SOCKET` or the remote desktop portal
+    Ei,
+    /// An absolute pointer device created through `/dev/uinput`
+    Uinput,
+}
+
+impl BackendKind {
+    pub const NAMES: &str = "auto, wlr, ei or uinput";
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(BackendKind::Auto),
+            "wlr" => Some(BackendKind::Wlr),
+            "ei" => Some(BackendKind::Ei),
+            "uinput" => Some(BackendKind::Uinput),
+            _ => None,
+        }
+    }
+
+    /// Connects to the backend, or to the first one that works for `Auto`.
+    pub fn connect(
+        self,
+        app: &AppData,
+        qh: &QueueHandle<AppData>,
+    ) -> Result<Box<dyn PointerBackend>, String> {
+        match self {
+            BackendKind::Wlr => {
+                let manager = app
+                    .virtual_pointer_manager
+                    .as_ref()
+                    .ok_or("the compositor doesn't support zwlr_virtual_pointer_manager_v1")?;
+                Ok(Box::new(WlrPointer::new(manager, qh)))
+            }
+            BackendKind::Ei => Ok(Box::new(ei::EiPointer::connect()?)),
+            BackendKind::Uinput => Ok(Box::new(uinput::UinputPointer::create()?)),
+            BackendKind::Auto => {
+                let mut errors = Vec::new();
+                for kind in [BackendKind::Wlr, BackendKind::Ei, BackendKind::Uinput] {
+                    match kind.connect(app, qh) {
+                        Ok(backend) => return Ok(backend),
+                        Err(err) => errors.push(format!("{}: {err}", kind.name())),
+                    }
+                }
+                Err(format!(
+                    "no way to move the pointer ({})",
+                    errors.join(", ")
+                ))
+            }
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BackendKind::Auto => "auto",
+            BackendKind::Wlr => "wlr",
+            BackendKind::Ei => "ei",
+            BackendKind::Uinput => "uinput",
+        }
+    }
+}
+
+/// Through `zwlr_virtual_pointer_v1`. Requests go out with the other
+/// Wayland requests when the event queue is flushed.
+#[derive(Debug)]
+pub struct WlrPointer {
+    pointer: ZwlrVirtualPointerV1,
+}
+
+impl WlrPointer {
+    pub fn new(manager: &ZwlrVirtualPointerManagerV1, qh: &QueueHandle<AppData>) -> Self {
+        Self {
+            pointer: manager.create_virtual_pointer(None, qh, ()),
+        }
+    }
+}
+
+impl PointerBackend for WlrPointer {
+    fn motion_absolute(&mut self, (x, y): (i32, i32), (left, top, width, height): Extents) {
+        // Relative to the top left corner of the layout
+        self.pointer.motion_absolute(
+            timestamp(),
+            (x - left) as u32,
+            (y - top) as u32,
+            width,
+            height,
+        );
+    }
+
+    fn motion(&mut self, dx: f64, dy: f64) {
+        self.pointer.motion(timestamp(), dx, dy);
+    }
+
+    fn button(&mut self, button: u32, state: ButtonState) {
+        self.pointer.button(timestamp(), button, state);
+    }
+
+    fn scroll(&mut self, axis: Axis, direction: i32, step: f64) {
+        self.pointer.axis_source(AxisSource::Wheel);
+        self.pointer
+            .axis_discrete(timestamp(), axis, step * direction as f64, direction);
+    }
+
+    fn frame(&mut self) {
+        self.pointer.frame();
+    }
+}
+
+impl Drop for WlrPointer {
+    fn drop(&mut self) {
+        self.pointer.destroy();
+    }
+}
@@ -0,0 +1,793 @@
+// A client for the wire protocol of libei, the way KDE and GNOME take
+// emulated input. Only what nomouse sends is implemented: one sender
+// context that binds the pointer capabilities of every seat and uses the
+// devices the server creates for them.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    io::{Read, Write},
+    os::{fd::AsFd, unix::net::UnixStream},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use nix::{
+    poll::{PollFd, PollFlags, PollTimeout, poll},
+    time::{ClockId, clock_gettime},
+};
+use wayland_client::protocol::wl_pointer::{Axis, ButtonState};
+
+use super::{Extents, PointerBackend, portal};
+
+/// How long the server gets to offer a device to move the pointer with.
+const SETUP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The object every connection starts out with.
+const HANDSHAKE: u64 = 0;
+
+/// Object id, length and opcode.
+const HEADER_LEN: usize = 16;
+
+/// Interfaces and the versions of them this client speaks.
+const INTERFACE_VERSIONS: [(&str, u32); 9] = [
+    ("ei_connection", 1),
+    ("ei_callback", 1),
+    ("ei_pingpong", 1),
+    ("ei_seat", 1),
+    ("ei_device", 1),
+    ("ei_pointer", 1),
+    ("ei_pointer_absolute", 1),
+    ("ei_scroll", 1),
+    ("ei_button", 1),
+];
+
+/// `ei_handshake.context_type` of a context that sends events.
+const CONTEXT_TYPE_SENDER: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Interface {
+    Connection,
+    Seat,
+    Device,
+    Pointer,
+    PointerAbsolute,
+    Scroll,
+    Button,
+    /// Offered by the server but not used here
+    Other,
+}
+
+impl Interface {
+    fn from_name(name: &str) -> Self {
+        match name {
+            "ei_pointer" => Interface::Pointer,
+            "ei_pointer_absolute" => Interface::PointerAbsolute,
+            "ei_scroll" => Interface::Scroll,
+            "ei_button" => Interface::Button,
+            _ => Interface::Other,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Device {
+    /// The objects of the interfaces the device has
+    interfaces: HashMap<Interface, u64>,
+    /// Events may only be sent while the server has the device resumed
+    resumed: bool,
+    emulating: bool,
+    /// Events were sent since the last frame
+    pending_frame: bool,
+}
+
+enum Arg<'a> {
+    Uint(u32),
+    Int(i32),
+    Float(f32),
+    Uint64(u64),
+    String(&'a str),
+}
+
+/// Reads the arguments of a message one after the other.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let Some((taken, rest)) = self.bytes.split_first_chunk() else {
+            return Err("message too short".to_string());
+        };
+        self.bytes = rest;
+        Ok(*taken)
+    }
+
+    fn uint(&mut self) -> Result<u32, String> {
+        self.take().map(u32::from_ne_bytes)
+    }
+
+    fn uint64(&mut self) -> Result<u64, String> {
+        self.take().map(u64::from_ne_bytes)
+    }
+
+    /// Length including the terminating nul, then the bytes padded to a
+    /// multiple of 4.
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.uint()? as usize;
+        let padded = len.next_multiple_of(4);
+        if self.bytes.len() < padded {
+            return Err("message too short".to_string());
+        }
+        let (bytes, rest) = self.bytes.split_at(padded);
+        self.bytes = rest;
+        let bytes = &bytes[..len.saturating_sub(1)];
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+/// A message as it arrived, see `split_message`.
+struct Message<'a> {
+    object: u64,
+    opcode: u32,
+    args: &'a [u8],
+    /// Including the header
+    len: usize,
+}
+
+/// Splits the first complete message off `bytes`.
+fn split_message(bytes: &[u8]) -> Result<Option<Message<'_>>, String> {
+    if bytes.len() < HEADER_LEN {
+        return Ok(None);
+    }
+    let mut header = Reader { bytes };
+    let object = header.uint64()?;
+    let len = header.uint()? as usize;
+    let opcode = header.uint()?;
+    if len < HEADER_LEN || !len.is_multiple_of(4) {
+        return Err(format!("invalid message length {len}"));
+    }
+    if bytes.len() < len {
+        return Ok(None);
+    }
+    Ok(Some(Message {
+        object,
+        opcode,
+        args: &bytes[HEADER_LEN..len],
+        len,
+    }))
+}
+
+fn encode(object: u64, opcode: u32, args: &[Arg]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for arg in args {
+        match arg {
+            Arg::Uint(value) => body.extend(value.to_ne_bytes()),
+            Arg::Int(value) => body.extend(value.to_ne_bytes()),
+            Arg::Float(value) => body.extend(value.to_ne_bytes()),
+            Arg::Uint64(value) => body.extend(value.to_ne_bytes()),
+            Arg::String(value) => {
+                body.extend((value.len() as u32 + 1).to_ne_bytes());
+                body.extend(value.as_bytes());
+                body.push(0);
+                body.resize(body.len().next_multiple_of(4), 0);
+            }
+        }
+    }
+    let mut message = Vec::with_capacity(HEADER_LEN + body.len());
+    message.extend(object.to_ne_bytes());
+    message.extend(((HEADER_LEN + body.len()) as u32).to_ne_bytes());
+    message.extend(opcode.to_ne_bytes());
+    message.extend(body);
+    message
+}
+
+/// `$LIBEI_SOCKET`, relative to `$XDG_RUNTIME_DIR` unless it is absolute.
+/// `None` when it isn't set.
+fn socket_path() -> Result<Option<PathBuf>, String> {
+    let Some(socket) = env::var_os("LIBEI_SOCKET").filter(|socket| !socket.is_empty()) else {
+        return Ok(None);
+    };
+    let socket = PathBuf::from(socket);
+    if socket.is_absolute() {
+        return Ok(Some(socket));
+    }
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR").ok_or("XDG_RUNTIME_DIR is not set")?;
+    Ok(Some(PathBuf::from(runtime_dir).join(socket)))
+}
+
+/// Microseconds on the monotonic clock, the timestamps of frames.
+fn now_micros() -> u64 {
+    let now = clock_gettime(ClockId::CLOCK_MONOTONIC).unwrap();
+    now.tv_sec() as u64 * 1_000_000 + now.tv_nsec() as u64 / 1000
+}
+
+#[derive(Debug)]
+pub struct EiPointer {
+    stream: UnixStream,
+    /// Keeps the EIS connection alive when it came from the portal
+    _portal: Option<portal::Session>,
+    /// Received bytes that don't make up a complete message yet
+    incoming: Vec<u8>,
+    /// The interface of every object the server told us about, along with
+    /// the device it belongs to for the interfaces of devices
+    objects: HashMap<u64, (Interface, u64)>,
+    /// The capabilities each seat offers that we want, bound once the seat
+    /// is done announcing them
+    seat_capabilities: HashMap<u64, u64>,
+    devices: BTreeMap<u64, Device>,
+    /// The serial of the last event that had one, which requests refer to
+    last_serial: u32,
+    /// Counts the times emulation was started
+    sequence: u32,
+    connection: Option<u64>,
+    /// Why the server disconnected us, or why we gave up on it
+    error: Option<String>,
+    /// Where the last motion went, for moving relative to it without
+    /// `ei_pointer`
+    position: Option<(f64, f64)>,
+}
+
+impl EiPointer {
+    /// Connects to the EIS server at `$LIBEI_SOCKET`, or the one of the
+    /// remote desktop portal without it, and waits until it offers the
+    /// devices needed to click.
+    pub fn connect() -> Result<Self, String> {
+        let mut pointer = match socket_path()? {
+            Some(path) => {
+                let stream = UnixStream::connect(&path)
+                    .map_err(|err| format!("{}: {err}", path.display()))?;
+                Self::new(stream)
+            }
+            None => {
+                let (session, fd) = portal::connect_to_eis()?;
+                let mut pointer = Self::new(UnixStream::from(fd));
+                pointer._portal = Some(session);
+                pointer
+            }
+        };
+        pointer.wait_for_devices()?;
+        Ok(pointer)
+    }
+
+    fn new(stream: UnixStream) -> Self {
+        let mut objects = HashMap::new();
+        objects.insert(HANDSHAKE, (Interface::Other, 0));
+        Self {
+            stream,
+            _portal: None,
+            incoming: Vec::new(),
+            objects,
+            seat_capabilities: HashMap::new(),
+            devices: BTreeMap::new(),
+            last_serial: 0,
+            sequence: 0,
+            connection: None,
+            error: None,
+            position: None,
+        }
+    }
+
+    fn wait_for_devices(&mut self) -> Result<(), String> {
+        let deadline = Instant::now() + SETUP_TIMEOUT;
+        while self.device_with(Interface::PointerAbsolute).is_none()
+            || self.device_with(Interface::Button).is_none()
+        {
+            if let Some(err) = &self.error {
+                return Err(err.clone());
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err("the EIS server didn't offer a pointer to click with".to_string());
+            }
+            self.receive(PollTimeout::try_from(left).unwrap_or(PollTimeout::MAX))?;
+        }
+        Ok(())
+    }
+
+    /// Handles the messages that arrive within `timeout`.
+    fn receive(&mut self, timeout: PollTimeout) -> Result<(), String> {
+        let mut fds = [PollFd::new(self.stream.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, timeout) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(err) => return Err(format!("poll: {err}")),
+        }
+        let mut buf = [0; 4096];
+        let len = self
+            .stream
+            .read(&mut buf)
+            .map_err(|err| format!("EIS connection: {err}"))?;
+        if len == 0 {
+            return Err("the EIS server hung up".to_string());
+        }
+        self.incoming.extend_from_slice(&buf[..len]);
+        let mut consumed = 0;
+        while let Some(message) = split_message(&self.incoming[consumed..])? {
+            let (object, opcode, args) = (message.object, message.opcode, message.args.to_vec());
+            consumed += message.len;
+            self.handle(object, opcode, Reader { bytes: &args })?;
+        }
+        self.incoming.drain(..consumed);
+        Ok(())
+    }
+
+    /// Handles what arrived since the last time without waiting, e.g. pings
+    /// and devices being paused. Errors are kept for `send` to report.
+    fn receive_pending(&mut self) {
+        if self.error.is_none()
+            && let Err(err) = self.receive(PollTimeout::ZERO)
+        {
+            self.error = Some(err);
+        }
+    }
+
+    fn handle(&mut self, object: u64, opcode: u32, mut args: Reader) -> Result<(), String> {
+        let Some(&(interface, device)) = self.objects.get(&object) else {
+            // Destroyed on our side already
+            return Ok(());
+        };
+        match (object, interface, opcode) {
+            // ei_handshake.handshake_version, the server's cue to introduce
+            // ourselves
+            (HANDSHAKE, _, 0) => {
+                self.send(HANDSHAKE, 0, &[Arg::Uint(1)]);
+                self.send(HANDSHAKE, 2, &[Arg::Uint(CONTEXT_TYPE_SENDER)]);
+                self.send(HANDSHAKE, 3, &[Arg::String("nomouse")]);
+                for (name, version) in INTERFACE_VERSIONS {
+                    self.send(HANDSHAKE, 4, &[Arg::String(name), Arg::Uint(version)]);
+                }
+                self.send(HANDSHAKE, 1, &[]);
+            }
+            // ei_handshake.connection
+            (HANDSHAKE, _, 2) => {
+                self.last_serial = args.uint()?;
+                let id = args.uint64()?;
+                self.objects.insert(id, (Interface::Connection, 0));
+                self.connection = Some(id);
+            }
+            // ei_connection.disconnected
+            (_, Interface::Connection, 0) => {
+                self.last_serial = args.uint()?;
+                let _reason = args.uint()?;
+                let explanation = args.string()?;
+                return Err(format!("the EIS server disconnected us: {explanation}"));
+            }
+            // ei_connection.seat
+            (_, Interface::Connection, 1) => {
+                let id = args.uint64()?;
+                self.objects.insert(id, (Interface::Seat, 0));
+                self.seat_capabilities.insert(id, 0);
+            }
+            // ei_connection.ping, answered with ei_pingpong.done
+            (_, Interface::Connection, 3) => {
+                let id = args.uint64()?;
+                self.send(id, 0, &[Arg::Uint64(0)]);
+            }
+            // ei_seat.destroyed
+            (_, Interface::Seat, 0) => {
+                self.last_serial = args.uint()?;
+                self.objects.remove(&object);
+                self.seat_capabilities.remove(&object);
+            }
+            // ei_seat.capability
+            (_, Interface::Seat, 2) => {
+                let mask = args.uint64()?;
+                if Interface::from_name(&args.string()?) != Interface::Other {
+                    *self.seat_capabilities.entry(object).or_default() |= mask;
+                }
+            }
+            // ei_seat.done, bind everything to do with pointers
+            (_, Interface::Seat, 3) => {
+                let capabilities = self.seat_capabilities.get(&object).copied();
+                self.send(object, 1, &[Arg::Uint64(capabilities.unwrap_or_default())]);
+            }
+            // ei_seat.device
+            (_, Interface::Seat, 4) => {
+                let id = args.uint64()?;
+                self.objects.insert(id, (Interface::Device, id));
+                self.devices.insert(id, Device::default());
+            }
+            // ei_device.destroyed
+            (_, Interface::Device, 0) => {
+                self.last_serial = args.uint()?;
+                self.objects.retain(|_, (_, owner)| *owner != object);
+                self.devices.remove(&object);
+            }
+            // ei_device.interface
+            (_, Interface::Device, 5) => {
+                let id = args.uint64()?;
+                let interface = Interface::from_name(&args.string()?);
+                self.objects.insert(id, (interface, object));
+                if let Some(device) = self.devices.get_mut(&object) {
+                    device.interfaces.insert(interface, id);
+                }
+            }
+            // ei_device.resumed
+            (_, Interface::Device, 7) => {
+                self.last_serial = args.uint()?;
+                if let Some(device) = self.devices.get_mut(&object) {
+                    device.resumed = true;
+                }
+            }
+            // ei_device.paused, emulation has to start over once resumed
+            (_, Interface::Device, 8) => {
+                self.last_serial = args.uint()?;
+                if let Some(device) = self.devices.get_mut(&object) {
+                    device.resumed = false;
+                    device.emulating = false;
+                }
+            }
+            // destroyed of the interfaces of a device
+            (
+                _,
+                Interface::Pointer
+                | Interface::PointerAbsolute
+                | Interface::Scroll
+                | Interface::Button
+                | Interface::Other,
+                0,
+            ) => {
+                self.last_serial = args.uint()?;
+                self.objects.remove(&object);
+                if let Some(device) = self.devices.get_mut(&device) {
+                    device.interfaces.retain(|_, id| *id != object);
+                }
+            }
+            // Names, regions and such aren't needed
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, object: u64, opcode: u32, args: &[Arg]) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(err) = self.stream.write_all(&encode(object, opcode, args)) {
+            self.error = Some(format!("EIS connection: {err}"));
+        }
+    }
+
+    /// The first resumed device that has `interface`.
+    fn device_with(&self, interface: Interface) -> Option<u64> {
+        self.devices
+            .iter()
+            .find(|(_, device)| device.resumed && device.interfaces.contains_key(&interface))
+            .map(|(id, _)| *id)
+    }
+
+    /// Sends a request of `interface` on a device that has it, starting
+    /// emulation on the device first when needed. Returns whether there was
+    /// such a device.
+    fn send_event(&mut self, interface: Interface, opcode: u32, args: &[Arg]) -> bool {
+        self.receive_pending();
+        let Some(id) = self.device_with(interface) else {
+            return false;
+        };
+        let device = self.devices.get_mut(&id).unwrap();
+        let object = device.interfaces[&interface];
+        let start_emulating = !device.emulating;
+        device.emulating = true;
+        device.pending_frame = true;
+        if start_emulating {
+            self.sequence += 1;
+            let (serial, sequence) = (self.last_serial, self.sequence);
+            // ei_device.start_emulating
+            self.send(id, 1, &[Arg::Uint(serial), Arg::Uint(sequence)]);
+        }
+        self.send(object, opcode, args);
+        if let Some(err) = self.error.take() {
+            eprintln!("nomouse: {err}");
+            // Only reported once, give up on the connection after that
+            self.devices.clear();
+        }
+        true
+    }
+}
+
+impl PointerBackend for EiPointer {
+    fn motion_absolute(&mut self, (x, y): (i32, i32), _extents: Extents) {
+        // ei_pointer_absolute.motion_absolute, in the global compositor space
+        // like our positions
+        self.send_event(
+            Interface::PointerAbsolute,
+            1,
+            &[Arg::Float(x as f32), Arg::Float(y as f32)],
+        );
+        self.position = Some((x as f64, y as f64));
+    }
+
+    fn motion(&mut self, dx: f64, dy: f64) {
+        let position = self.position.map(|(x, y)| (x + dx, y + dy));
+        // ei_pointer.motion_relative
+        let relative = [Arg::Float(dx as f32), Arg::Float(dy as f32)];
+        if !self.send_event(Interface::Pointer, 1, &relative)
+            && let Some((x, y)) = position
+        {
+            let absolute = [Arg::Float(x as f32), Arg::Float(y as f32)];
+            self.send_event(Interface::PointerAbsolute, 1, &absolute);
+        }
+        self.position = position;
+    }
+
+    fn button(&mut self, button: u32, state: ButtonState) {
+        let pressed = state == ButtonState::Pressed;
+        // ei_button.button
+        self.send_event(
+            Interface::Button,
+            1,
+            &[Arg::Uint(button), Arg::Uint(pressed as u32)],
+        );
+    }
+
+    fn scroll(&mut self, axis: Axis, direction: i32, _step: f64) {
+        // In 120ths of a wheel step, positive is down and right like Wayland
+        let (x, y) = match axis {
+            Axis::VerticalScroll => (0, direction * 120),
+            _ => (direction * 120, 0),
+        };
+        // ei_scroll.scroll_discrete
+        self.send_event(Interface::Scroll, 2, &[Arg::Int(x), Arg::Int(y)]);
+    }
+
+    fn frame(&mut self) {
+        let pending: Vec<u64> = self
+            .devices
+            .iter_mut()
+            .filter(|(_, device)| device.pending_frame)
+            .map(|(id, device)| {
+                device.pending_frame = false;
+                *id
+            })
+            .collect();
+        for id in pending {
+            // ei_device.frame
+            let serial = self.last_serial;
+            self.send(id, 3, &[Arg::Uint(serial), Arg::Uint64(now_micros())]);
+        }
+    }
+}
+
+impl Drop for EiPointer {
+    fn drop(&mut self) {
+        let emulating: Vec<u64> = self
+            .devices
+            .iter()
+            .filter(|(_, device)| device.emulating)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in emulating {
+            // ei_device.stop_emulating
+            let serial = self.last_serial;
+            self.send(id, 2, &[Arg::Uint(serial)]);
+        }
+        if let Some(connection) = self.connection {
+            // ei_connection.disconnect
+            self.send(connection, 1, &[]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use smithay_client_toolkit::seat::pointer::BTN_LEFT;
+
+    use super::*;
+
+    const CONNECTION: u64 = 0xff00_0000_0000_0001;
+    const SEAT: u64 = 0xff00_0000_0000_0002;
+    const DEVICE: u64 = 0xff00_0000_0000_0003;
+    const POINTER_ABSOLUTE: u64 = 0xff00_0000_0000_0004;
+    const BUTTON: u64 = 0xff00_0000_0000_0005;
+
+    // The mock server below lays messages out by hand following the protocol
+    // documentation instead of going through `encode` and `split_message`,
+    // so a mistake in those shows up instead of cancelling out.
+
+    /// Object id, length including the header, opcode and the arguments,
+    /// all in native byte order.
+    fn message(object: u64, opcode: u32, args: &[&[u8]]) -> Vec<u8> {
+        let args = args.concat();
+        let len = 16 + args.len() as u32;
+        let header: [&[u8]; 3] = [
+            &object.to_ne_bytes(),
+            &len.to_ne_bytes(),
+            &opcode.to_ne_bytes(),
+        ];
+        [&header.concat(), &args[..]].concat()
+    }
+
+    /// Length including the nul, then the string padded to 4 bytes.
+    fn string(value: &str) -> Vec<u8> {
+        let mut bytes = (value.len() as u32 + 1).to_ne_bytes().to_vec();
+        bytes.extend(value.as_bytes());
+        bytes.extend(vec![0; 4 - value.len() % 4]);
+        bytes
+    }
+
+    /// Reads one message of the client, `None` once it hung up.
+    fn read_message(stream: &mut UnixStream) -> Option<(u64, u32, Vec<u8>)> {
+        let mut header = [0; 16];
+        stream.read_exact(&mut header).ok()?;
+        let object = u64::from_ne_bytes(header[..8].try_into().unwrap());
+        let len = u32::from_ne_bytes(header[8..12].try_into().unwrap());
+        let opcode = u32::from_ne_bytes(header[12..].try_into().unwrap());
+        let mut args = vec![0; len as usize - 16];
+        stream.read_exact(&mut args).unwrap();
+        Some((object, opcode, args))
+    }
+
+    /// Reads messages until `until` or the client hangs up.
+    fn read_until(stream: &mut UnixStream, until: Option<(u64, u32)>) -> Vec<(u64, u32, Vec<u8>)> {
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(stream) {
+            let done = until == Some((message.0, message.1));
+            messages.push(message);
+            if done {
+                break;
+            }
+        }
+        messages
+    }
+
+    /// Plays an EIS server with one seat that offers a device with an
+    /// absolute pointer and buttons, returns what the client sent after
+    /// the device was resumed.
+    fn serve(mut stream: UnixStream) -> Vec<(u64, u32, Vec<u8>)> {
+        let mut reader = stream.try_clone().unwrap();
+        let uint = |value: u32| value.to_ne_bytes().to_vec();
+        let uint64 = |value: u64| value.to_ne_bytes().to_vec();
+        let mut send = |object, opcode, args: &[&[u8]]| {
+            stream.write_all(&message(object, opcode, args)).unwrap();
+        };
+        // ei_handshake.handshake_version
+        send(HANDSHAKE, 0, &[&uint(1)]);
+        let handshake = read_until(&mut reader, Some((HANDSHAKE, 1)));
+        assert_eq!(handshake[0], (HANDSHAKE, 0, uint(1)));
+        assert!(handshake.contains(&(HANDSHAKE, 2, uint(2))));
+        assert!(handshake.contains(&(HANDSHAKE, 3, string("nomouse"))));
+        let button = [string("ei_button"), uint(1)].concat();
+        assert!(handshake.contains(&(HANDSHAKE, 4, button)));
+
+        // ei_handshake.connection, ei_connection.seat
+        send(HANDSHAKE, 2, &[&uint(1), &uint64(CONNECTION), &uint(1)]);
+        send(CONNECTION, 1, &[&uint64(SEAT), &uint(1)]);
+        // ei_seat.capability, only pointer ones are bound
+        send(SEAT, 2, &[&uint64(1), &string("ei_pointer_absolute")]);
+        send(SEAT, 2, &[&uint64(4), &string("ei_button")]);
+        send(SEAT, 2, &[&uint64(8), &string("ei_keyboard")]);
+        // ei_seat.done, answered with ei_seat.bind
+        send(SEAT, 3, &[]);
+        let bind = read_until(&mut reader, Some((SEAT, 1)));
+        assert_eq!(bind.last().unwrap().2, uint64(5));
+
+        // ei_seat.device, ei_device.interface, done and resumed
+        send(SEAT, 4, &[&uint64(DEVICE), &uint(1)]);
+        let pointer_absolute = string("ei_pointer_absolute");
+        send(
+            DEVICE,
+            5,
+            &[&uint64(POINTER_ABSOLUTE), &pointer_absolute, &uint(1)],
+        );
+        send(
+            DEVICE,
+            5,
+            &[&uint64(BUTTON), &string("ei_button"), &uint(1)],
+        );
+        send(DEVICE, 6, &[]);
+        send(DEVICE, 7, &[&uint(2)]);
+        read_until(&mut reader, None)
+    }
+
+    #[test]
+    fn lays_out_messages() {
+        let mut expected = Vec::new();
+        expected.extend(7u64.to_ne_bytes());
+        expected.extend(44u32.to_ne_bytes());
+        expected.extend(4u32.to_ne_bytes());
+        // "ei_button" and its nul padded to 12 bytes
+        expected.extend(10u32.to_ne_bytes());
+        expected.extend(b"ei_button\0\0\0");
+        expected.extend(1.5f32.to_ne_bytes());
+        expected.extend(u64::MAX.to_ne_bytes());
+        let args = [
+            Arg::String("ei_button"),
+            Arg::Float(1.5),
+            Arg::Uint64(u64::MAX),
+        ];
+        let bytes = encode(7, 4, &args);
+        assert_eq!(bytes, expected);
+
+        let message = split_message(&bytes).unwrap().unwrap();
+        assert_eq!((message.object, message.opcode, message.len), (7, 4, 44));
+        let mut reader = Reader {
+            bytes: message.args,
+        };
+        assert_eq!(reader.string().unwrap(), "ei_button");
+        assert_eq!(reader.take().map(f32::from_ne_bytes).unwrap(), 1.5);
+        assert_eq!(reader.uint64().unwrap(), u64::MAX);
+        assert!(reader.uint().is_err());
+
+        // Incomplete messages wait for the rest, invalid lengths are errors
+        assert!(split_message(&bytes[..43]).unwrap().is_none());
+        let mut invalid = bytes.clone();
+        invalid[8..12].copy_from_slice(&8u32.to_ne_bytes());
+        assert!(split_message(&invalid).is_err());
+    }
+
+    #[test]
+    fn clicks_through_an_eis_server() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || serve(server));
+        let mut pointer = EiPointer::new(client);
+        pointer.wait_for_devices().unwrap();
+        pointer.motion_absolute((100, 50), (0, 0, 1920, 1080));
+        pointer.button(BTN_LEFT, ButtonState::Pressed);
+        pointer.frame();
+        drop(pointer);
+
+        let sent = server.join().unwrap();
+        let requests: Vec<(u64, u32)> = sent
+            .iter()
+            .map(|(object, opcode, _)| (*object, *opcode))
+            .collect();
+        assert_eq!(
+            requests,
+            [
+                (DEVICE, 1),           // start_emulating
+                (POINTER_ABSOLUTE, 1), // motion_absolute
+                (BUTTON, 1),           // button
+                (DEVICE, 3),           // frame
+                (DEVICE, 2),           // stop_emulating
+                (CONNECTION, 1),       // disconnect
+            ]
+        );
+        // The serial the device was resumed with and the first sequence
+        assert_eq!(sent[0].2, [2u32.to_ne_bytes(), 1u32.to_ne_bytes()].concat());
+        let position = [100f32.to_ne_bytes(), 50f32.to_ne_bytes()].concat();
+        assert_eq!(sent[1].2, position);
+        assert_eq!(
+            sent[2].2,
+            [BTN_LEFT.to_ne_bytes(), 1u32.to_ne_bytes()].concat()
+        );
+    }
+
+    /// Against a real server, like the `eis-demo-server` of libei, which
+    /// hangs up on requests that break the protocol. Skipped unless
+    /// `$LIBEI_SOCKET` points at one.
+    #[test]
+    #[ignore = "needs an EIS server at $LIBEI_SOCKET"]
+    fn clicks_through_a_real_eis_server() {
+        if env::var_os("LIBEI_SOCKET").is_none_or(|socket| socket.is_empty()) {
+            eprintln!("LIBEI_SOCKET isn't set, skipping");
+            return;
+        }
+        let mut pointer = EiPointer::connect().unwrap();
+        pointer.motion_absolute((100, 50), (0, 0, 1920, 1080));
+        pointer.frame();
+        pointer.button(BTN_LEFT, ButtonState::Pressed);
+        pointer.frame();
+        pointer.button(BTN_LEFT, ButtonState::Released);
+        pointer.frame();
+        pointer.scroll(Axis::VerticalScroll, 1, 0.0);
+        pointer.motion(5.0, -5.0);
+        pointer.frame();
+
+        // Give the server time to object
+        let deadline = Instant::now() + Duration::from_millis(500);
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            pointer
+                .receive(PollTimeout::try_from(left).unwrap_or(PollTimeout::MAX))
+                .unwrap();
+        }
+        assert_eq!(pointer.error, None);
+        assert!(pointer.device_with(Interface::Button).is_some());
+    }
+}
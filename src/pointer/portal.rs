@@ -0,0 +1,328 @@
+// Asks the remote desktop portal for a connection to the compositor's EIS
+// server, for compositors that don't hand out `$LIBEI_SOCKET`.
+
+use std::{collections::HashMap, os::fd::OwnedFd};
+
+use zbus::{
+    MatchRule, Message,
+    blocking::{Connection, MessageIterator},
+    export::serde::Serialize,
+    message::Type,
+    zvariant::{self, DynamicType, ObjectPath, OwnedObjectPath, OwnedValue, Value},
+};
+
+use crate::state;
+
+const DESTINATION: &str = "org.freedesktop.portal.Desktop";
+const PATH: &str = "/org/freedesktop/portal/desktop";
+const REMOTE_DESKTOP: &str = "org.freedesktop.portal.RemoteDesktop";
+const REQUEST: &str = "org.freedesktop.portal.Request";
+
+/// `types` of `SelectDevices`.
+const DEVICE_POINTER: u32 = 2;
+
+/// `persist_mode` of `SelectDevices`, until the user revokes it.
+const PERSIST_PERMANENTLY: u32 = 2;
+
+/// A remote desktop session, which lasts as long as its D-Bus connection.
+#[derive(Debug)]
+pub struct Session {
+    _connection: Connection,
+}
+
+/// Starts a remote desktop session that may control the pointer and connects
+/// to its EIS server. The user is asked for permission the first time, the
+/// portal hands out a token to skip that next time.
+pub fn connect_to_eis() -> Result<(Session, OwnedFd), String> {
+    let connection =
+        Connection::session().map_err(|err| format!("remote desktop portal: {err}"))?;
+    let restore_token = state::load_restore_token().unwrap_or_else(|err| {
+        eprintln!("nomouse: can't load the portal's restore token: {err}");
+        None
+    });
+    let (fd, restore_token) = start(&connection, restore_token.as_deref())?;
+    if let Some(restore_token) = restore_token
+        && let Err(err) = state::save_restore_token(&restore_token)
+    {
+        eprintln!("nomouse: can't save the portal's restore token: {err}");
+    }
+    Ok((
+        Session {
+            _connection: connection,
+        },
+        fd,
+    ))
+}
+
+/// Goes through the calls of a remote desktop session up to the EIS
+/// connection. Returns it along with the next restore token.
+fn start(
+    connection: &Connection,
+    restore_token: Option<&str>,
+) -> Result<(OwnedFd, Option<String>), String> {
+    let results = request(connection, "CreateSession", |token| {
+        (options([
+            ("handle_token", Value::from(token)),
+            ("session_handle_token", Value::from("nomouse")),
+        ]),)
+    })?;
+    let session_handle: String = result(&results, "session_handle")
+        .ok_or("the remote desktop portal didn't send a session handle")?;
+    let session = ObjectPath::try_from(session_handle.as_str())
+        .map_err(|err| format!("remote desktop portal: {err}"))?;
+
+    request(connection, "SelectDevices", |token| {
+        let mut options = options([
+            ("handle_token", Value::from(token)),
+            ("types", Value::from(DEVICE_POINTER)),
+            ("persist_mode", Value::from(PERSIST_PERMANENTLY)),
+        ]);
+        if let Some(restore_token) = restore_token {
+            options.insert("restore_token", Value::from(restore_token));
+        }
+        (session.clone(), options)
+    })?;
+    let results = request(connection, "Start", |token| {
+        (
+            session.clone(),
+            "",
+            options([("handle_token", Value::from(token))]),
+        )
+    })?;
+
+    let fd: zvariant::OwnedFd = call(connection, "ConnectToEIS", &(&session, options([])))
+        .and_then(|reply| reply.body().deserialize())
+        .map_err(|err| format!("remote desktop portal: ConnectToEIS: {err}"))?;
+    Ok((fd.into(), result(&results, "restore_token")))
+}
+
+type Options<'a> = HashMap<&'a str, Value<'a>>;
+
+fn options<'a, const N: usize>(options: [(&'a str, Value<'a>); N]) -> Options<'a> {
+    options.into_iter().collect()
+}
+
+fn result<T: TryFrom<OwnedValue>>(results: &HashMap<String, OwnedValue>, key: &str) -> Option<T> {
+    let value = results.get(key)?.try_clone().ok()?;
+    T::try_from(value).ok()
+}
+
+fn call<B>(connection: &Connection, method: &str, body: &B) -> zbus::Result<Message>
+where
+    B: Serialize + DynamicType,
+{
+    connection.call_method(Some(DESTINATION), PATH, Some(REMOTE_DESKTOP), method, body)
+}
+
+/// Calls `method` with the arguments `args` builds around a handle token,
+/// and waits for the response to the request it makes. Returns the results
+/// of the response.
+fn request<B>(
+    connection: &Connection,
+    method: &str,
+    args: impl FnOnce(String) -> B,
+) -> Result<HashMap<String, OwnedValue>, String>
+where
+    B: Serialize + DynamicType,
+{
+    let dbus_err = |err: zbus::Error| format!("remote desktop portal: {method}: {err}");
+    // Listening before the call, the response may arrive before its reply
+    let rule = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .interface(REQUEST)
+        .and_then(|rule| rule.member("Response"))
+        .and_then(|rule| rule.path_namespace(format!("{PATH}/request")))
+        .map_err(dbus_err)?
+        .build();
+    let mut responses =
+        MessageIterator::for_match_rule(rule, connection, None).map_err(dbus_err)?;
+    let token = format!("nomouse_{}", method.to_lowercase());
+    let handle: OwnedObjectPath = call(connection, method, &args(token))
+        .and_then(|reply| reply.body().deserialize())
+        .map_err(dbus_err)?;
+    let response = responses
+        .find_map(|message| {
+            message
+                .ok()
+                .filter(|message| message.header().path() == Some(&handle))
+        })
+        .ok_or_else(|| format!("remote desktop portal: {method}: no response"))?;
+    let (status, results): (u32, HashMap<String, OwnedValue>) =
+        response.body().deserialize().map_err(dbus_err)?;
+    match status {
+        0 => Ok(results),
+        1 => Err("permission to control the pointer was denied".to_string()),
+        _ => Err(format!("remote desktop portal: {method} failed")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        os::unix::net::UnixStream,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use zbus::{Guid, blocking::connection::Builder, interface, object_server::SignalEmitter};
+
+    use super::*;
+
+    /// The methods the portal was called with and their options.
+    type Calls = Vec<(String, HashMap<String, OwnedValue>)>;
+
+    /// What `start` returns.
+    type Outcome = Result<(OwnedFd, Option<String>), String>;
+
+    /// Plays the remote desktop portal, answering every request with
+    /// `status` and recording the options of the calls.
+    struct MockPortal {
+        status: u32,
+        calls: Arc<Mutex<Calls>>,
+        eis: Mutex<Option<UnixStream>>,
+    }
+
+    impl MockPortal {
+        async fn respond(
+            &self,
+            emitter: &SignalEmitter<'_>,
+            method: &str,
+            options: HashMap<String, OwnedValue>,
+            results: HashMap<&str, Value<'_>>,
+        ) -> OwnedObjectPath {
+            let token: String = result(&options, "handle_token").unwrap();
+            self.calls
+                .lock()
+                .unwrap()
+                .push((method.to_string(), options));
+            let handle = format!("{PATH}/request/client/{token}");
+            // Responses on other requests are ignored
+            for path in [format!("{PATH}/request/client/other"), handle.clone()] {
+                emitter
+                    .connection()
+                    .emit_signal(
+                        None::<()>,
+                        path.as_str(),
+                        REQUEST,
+                        "Response",
+                        &(self.status, &results),
+                    )
+                    .await
+                    .unwrap();
+            }
+            OwnedObjectPath::try_from(handle).unwrap()
+        }
+    }
+
+    #[interface(name = "org.freedesktop.portal.RemoteDesktop")]
+    impl MockPortal {
+        async fn create_session(
+            &self,
+            #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+            options: HashMap<String, OwnedValue>,
+        ) -> OwnedObjectPath {
+            let session = Value::from(format!("{PATH}/session/client/nomouse"));
+            let results = HashMap::from([("session_handle", session)]);
+            self.respond(&emitter, "CreateSession", options, results)
+                .await
+        }
+
+        async fn select_devices(
+            &self,
+            #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+            _session: ObjectPath<'_>,
+            options: HashMap<String, OwnedValue>,
+        ) -> OwnedObjectPath {
+            self.respond(&emitter, "SelectDevices", options, HashMap::new())
+                .await
+        }
+
+        async fn start(
+            &self,
+            #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+            _session: ObjectPath<'_>,
+            _parent_window: &str,
+            options: HashMap<String, OwnedValue>,
+        ) -> OwnedObjectPath {
+            let results = HashMap::from([("restore_token", Value::from("next"))]);
+            self.respond(&emitter, "Start", options, results).await
+        }
+
+        #[zbus(name = "ConnectToEIS")]
+        fn connect_to_eis(
+            &self,
+            _session: ObjectPath<'_>,
+            _options: HashMap<String, OwnedValue>,
+        ) -> zvariant::OwnedFd {
+            let eis = self.eis.lock().unwrap().take().unwrap();
+            OwnedFd::from(eis).into()
+        }
+    }
+
+    /// Starts a session with a portal answering `status`, returns the
+    /// outcome along with the calls the portal got and the server's end of
+    /// the EIS connection.
+    fn start_with(status: u32, restore_token: Option<&str>) -> (Outcome, Calls, UnixStream) {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (eis_client, eis_server) = UnixStream::pair().unwrap();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let portal = MockPortal {
+            status,
+            calls: calls.clone(),
+            eis: Mutex::new(Some(eis_client)),
+        };
+        let server = thread::spawn(move || {
+            Builder::async_io_unix_stream(server)
+                .server(Guid::generate())
+                .unwrap()
+                .p2p()
+                .serve_at(PATH, portal)
+                .unwrap()
+                .build()
+                .unwrap()
+        });
+        let connection = Builder::async_io_unix_stream(client).p2p().build().unwrap();
+        let _server = server.join().unwrap();
+        let outcome = start(&connection, restore_token);
+        let calls = calls.lock().unwrap().drain(..).collect();
+        (outcome, calls, eis_server)
+    }
+
+    #[test]
+    fn connects_to_eis() {
+        let (outcome, calls, mut eis) = start_with(0, Some("previous"));
+        let (fd, restore_token) = outcome.unwrap();
+        assert_eq!(restore_token.as_deref(), Some("next"));
+
+        let methods: Vec<&str> = calls.iter().map(|(method, _)| method.as_str()).collect();
+        assert_eq!(methods, ["CreateSession", "SelectDevices", "Start"]);
+        let select_devices = &calls[1].1;
+        assert_eq!(result::<u32>(select_devices, "types"), Some(DEVICE_POINTER));
+        assert_eq!(
+            result::<u32>(select_devices, "persist_mode"),
+            Some(PERSIST_PERMANENTLY)
+        );
+        assert_eq!(
+            result::<String>(select_devices, "restore_token").as_deref(),
+            Some("previous")
+        );
+
+        // The fd is the portal's end of the EIS connection
+        let mut client = UnixStream::from(fd);
+        eis.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[test]
+    fn reports_denied_permission() {
+        let (outcome, calls, _) = start_with(1, None);
+        assert_eq!(
+            outcome.unwrap_err(),
+            "permission to control the pointer was denied"
+        );
+        assert_eq!(calls.len(), 1);
+    }
+}
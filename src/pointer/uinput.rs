@@ -0,0 +1,161 @@
+// A pointer device of our own, created through `/dev/uinput`. Works on any
+// compositor as long as the device node is writable, e.g. for the `input`
+// group or with a udev rule.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    mem,
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    slice, thread,
+    time::Duration,
+};
+
+use smithay_client_toolkit::seat::pointer::{BTN_LEFT, BTN_MIDDLE, BTN_RIGHT};
+use wayland_client::protocol::wl_pointer::{Axis, ButtonState};
+
+use super::{Extents, PointerBackend};
+
+const PATH: &str = "/dev/uinput";
+
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_REL: u16 = 0x02;
+const EV_ABS: u16 = 0x03;
+const SYN_REPORT: u16 = 0x00;
+const REL_HWHEEL: u16 = 0x06;
+const REL_WHEEL: u16 = 0x08;
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const BUS_VIRTUAL: u16 = 0x06;
+
+/// The largest absolute coordinate, the compositor maps the range onto the
+/// whole layout.
+const ABS_MAX: i32 = 65535;
+
+/// How long it takes the compositor to pick up a new device, events sent
+/// before that are lost.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+nix::ioctl_none!(ui_dev_create, b'U', 1);
+nix::ioctl_none!(ui_dev_destroy, b'U', 2);
+nix::ioctl_write_ptr!(ui_dev_setup, b'U', 3, libc::uinput_setup);
+nix::ioctl_write_ptr!(ui_abs_setup, b'U', 4, libc::uinput_abs_setup);
+nix::ioctl_write_int!(ui_set_evbit, b'U', 100);
+nix::ioctl_write_int!(ui_set_keybit, b'U', 101);
+nix::ioctl_write_int!(ui_set_relbit, b'U', 102);
+nix::ioctl_write_int!(ui_set_absbit, b'U', 103);
+
+/// An absolute pointer with a wheel, like the tablet of a virtual machine.
+#[derive(Debug)]
+pub struct UinputPointer {
+    file: File,
+    /// Where the last absolute motion went, relative motion is absolute
+    /// motion from there since the device has no relative axes
+    position: Option<((f64, f64), Extents)>,
+}
+
+impl UinputPointer {
+    pub fn create() -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(PATH)
+            .map_err(|err| format!("{PATH}: {err}"))?;
+        let fd = file.as_raw_fd();
+        let setup = || -> nix::Result<()> {
+            unsafe {
+                ui_set_evbit(fd, EV_KEY.into())?;
+                for button in [BTN_LEFT, BTN_RIGHT, BTN_MIDDLE] {
+                    ui_set_keybit(fd, button as u64)?;
+                }
+                ui_set_evbit(fd, EV_REL.into())?;
+                ui_set_relbit(fd, REL_WHEEL.into())?;
+                ui_set_relbit(fd, REL_HWHEEL.into())?;
+                ui_set_evbit(fd, EV_ABS.into())?;
+                for axis in [ABS_X, ABS_Y] {
+                    ui_set_absbit(fd, axis.into())?;
+                    let mut abs_setup: libc::uinput_abs_setup = mem::zeroed();
+                    abs_setup.code = axis;
+                    abs_setup.absinfo.maximum = ABS_MAX;
+                    ui_abs_setup(fd, &abs_setup)?;
+                }
+                let mut dev_setup: libc::uinput_setup = mem::zeroed();
+                dev_setup.id.bustype = BUS_VIRTUAL;
+                for (dst, src) in dev_setup.name.iter_mut().zip(b"nomouse pointer") {
+                    *dst = *src as libc::c_char;
+                }
+                ui_dev_setup(fd, &dev_setup)?;
+                ui_dev_create(fd)?;
+            }
+            Ok(())
+        };
+        setup().map_err(|err| format!("{PATH}: {err}"))?;
+        thread::sleep(SETTLE_TIME);
+        Ok(Self {
+            file,
+            position: None,
+        })
+    }
+
+    fn emit(&mut self, type_: u16, code: u16, value: i32) {
+        let mut event: libc::input_event = unsafe { mem::zeroed() };
+        // The kernel fills in the time
+        event.type_ = type_;
+        event.code = code;
+        event.value = value;
+        let bytes = unsafe {
+            slice::from_raw_parts(
+                (&event as *const libc::input_event).cast::<u8>(),
+                mem::size_of::<libc::input_event>(),
+            )
+        };
+        if let Err(err) = self.file.write_all(bytes) {
+            eprintln!("nomouse: {PATH}: {err}");
+        }
+    }
+}
+
+impl PointerBackend for UinputPointer {
+    fn motion_absolute(&mut self, (x, y): (i32, i32), extents: Extents) {
+        let (left, top, width, height) = extents;
+        let scale = |offset: i32, length: u32| {
+            (offset as f64 / length.max(1) as f64 * ABS_MAX as f64).round() as i32
+        };
+        self.emit(EV_ABS, ABS_X, scale(x - left, width));
+        self.emit(EV_ABS, ABS_Y, scale(y - top, height));
+        self.position = Some(((x as f64, y as f64), extents));
+    }
+
+    fn motion(&mut self, dx: f64, dy: f64) {
+        let Some(((x, y), extents)) = self.position else {
+            return;
+        };
+        self.motion_absolute(((x + dx).round() as i32, (y + dy).round() as i32), extents);
+        // Keep the fractions for the next step
+        self.position = Some(((x + dx, y + dy), extents));
+    }
+
+    fn button(&mut self, button: u32, state: ButtonState) {
+        let pressed = state == ButtonState::Pressed;
+        self.emit(EV_KEY, button as u16, pressed as i32);
+    }
+
+    fn scroll(&mut self, axis: Axis, direction: i32, _step: f64) {
+        match axis {
+            // Positive is up for the wheel but down for Wayland
+            Axis::VerticalScroll => self.emit(EV_REL, REL_WHEEL, -direction),
+            _ => self.emit(EV_REL, REL_HWHEEL, direction),
+        }
+    }
+
+    fn frame(&mut self) {
+        self.emit(EV_SYN, SYN_REPORT, 0);
+    }
+}
+
+impl Drop for UinputPointer {
+    fn drop(&mut self) {
+        let _ = unsafe { ui_dev_destroy(self.file.as_raw_fd()) };
+    }
+}
@@ -1,14 +1,14 @@
-use std::{collections::HashMap, hash::Hash};
+use cosmic_text::{Attrs, FontSystem, Metrics, SwashCache, Weight};
 
-use cosmic_text::{Attrs, Buffer, Color, FontSystem, Metrics, Shaping, SwashCache};
+use crate::{
+    config::{Config, GridConfig, HintsConfig},
+    hints::{LabelStyle, shortest_labels},
+    render_utils::{draw_border, draw_spans, draw_text, scale_length, text_color},
+};
 
-use crate::render_utils::{alpha_multiply, draw_border, draw_rect};
-
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct FinalSelector {
-    // 24 boxes
-    // 3 rows
-    // 8 columns
+    // n_rows * n_cols boxes, one per key
     pub x: usize,
     pub y: usize,
     pub width: usize,
@@ -16,18 +16,21 @@ pub struct FinalSelector {
     pub n_rows: usize,
     pub n_cols: usize,
     pub depth: usize,
-    pub keycodes: Vec<u32>,
+    pub keys: Vec<char>,
+    pub output: u32,
 }
 
 impl FinalSelector {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        output: u32,
         x: usize,
         y: usize,
         width: usize,
         height: usize,
         n_rows: usize,
         n_cols: usize,
-        keycodes: Vec<u32>,
+        keys: Vec<char>,
     ) -> FinalSelector {
         Self {
             x,
@@ -37,7 +40,8 @@ impl FinalSelector {
             n_cols,
             n_rows,
             depth: 0,
-            keycodes,
+            keys,
+            output,
         }
     }
 
@@ -55,43 +59,69 @@ impl FinalSelector {
             n_rows: self.n_rows,
             n_cols: self.n_cols,
             depth: self.depth + 1,
-            keycodes: self.keycodes.clone(),
+            keys: self.keys.clone(),
+            output: self.output,
         }
     }
 
-    pub fn handle_input(&mut self, pressed_key: u32) {
-        let idx = match self.keycodes.iter().position(|key| *key == pressed_key) {
-            Some(idx) => idx,
-            None => return,
+    pub fn handle_input(&mut self, pressed_key: char) -> bool {
+        let idx = match self.keys.iter().position(|key| *key == pressed_key) {
+            Some(idx) if idx < self.n_rows * self.n_cols => idx,
+            _ => return false,
         };
 
         let col = idx % self.n_cols;
         let row = idx / self.n_cols;
-        dbg!(col, row);
         *self = self.select(col, row);
+        true
     }
 
-    pub fn draw(
-        &self,
-        buf: &mut [u8],
-        screen_width: usize,
-        font_system: &mut FontSystem,
-        swash_cache: &mut SwashCache,
-        keycode_symbols: &HashMap<u32, String>,
-    ) {
-        draw_border(buf, self.x, self.y, self.width, self.height, screen_width);
+    pub fn draw(&self, buf: &mut [u8], screen_width: usize, scale: f64, config: &Config) {
+        let colors = &config.colors;
+        let thickness = scale_length(1, scale);
+        draw_border(
+            buf,
+            self.x,
+            self.y,
+            self.width,
+            self.height,
+            screen_width,
+            thickness,
+            colors,
+        );
         for row in 0..self.n_rows {
             for col in 0..self.n_cols {
                 let width = self.width / self.n_cols;
                 let height = self.height / self.n_rows;
                 let x = self.x + width * col;
                 let y = self.y + height * row;
-                draw_border(buf, x, y, width, height, screen_width);
+                draw_border(buf, x, y, width, height, screen_width, thickness, colors);
             }
         }
     }
 }
 
+/// Grids are configured with landscape outputs in mind. On a portrait area
+/// rows and columns are swapped when that keeps the cells closer to the
+/// shape they would have with the area turned on its side.
+fn orient(rows: usize, columns: usize, width: usize, height: usize) -> (usize, usize) {
+    if height <= width {
+        return (rows, columns);
+    }
+    let cell_shape = |rows: usize, columns: usize, width: usize, height: usize| {
+        (width as f64 / columns as f64) / (height as f64 / rows as f64)
+    };
+    let landscape = cell_shape(rows, columns, height, width);
+    let distance = |shape: f64| (shape / landscape).ln().abs();
+    if distance(cell_shape(columns, rows, width, height))
+        < distance(cell_shape(rows, columns, width, height))
+    {
+        (columns, rows)
+    } else {
+        (rows, columns)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Rect {
     pub x: usize,
@@ -100,60 +130,71 @@ pub struct Rect {
     pub height: usize,
 }
 
-// Each box is assigned a sequence of two keycodes.
-#[derive(Debug)]
+// Each box is assigned a label, a sequence of keys that is never the start
+// of another one. See `LabelStyle` for how they are picked.
+#[derive(Debug, Clone)]
 pub struct InitialSelector {
-    keycodes: Vec<u32>,
-    rects: HashMap<(u32, u32), Rect>,
-    last_key: Option<u32>,
+    keys: Vec<char>,
+    /// In reading order
+    cells: Vec<(Vec<char>, Rect)>,
+    /// The start of a label typed so far
+    typed: Vec<char>,
+    output: u32,
+    final_rows: usize,
+    final_cols: usize,
 }
 
 impl InitialSelector {
     pub fn new(
-        keycodes: Vec<u32>,
-        n_rows: usize,
-        n_cols: usize,
+        output: u32,
+        hints: &HintsConfig,
+        grid: &GridConfig,
         screen_width: usize,
         screen_height: usize,
     ) -> Self {
-        let mut rects = HashMap::new();
-        for row in 0..n_rows {
-            let row_key = keycodes[row];
-            for col in 0..n_cols {
-                let col_key = keycodes[col];
-                let width = screen_width / n_rows;
-                let height = screen_height / n_cols;
-                let x = width * row;
-                let y = height * col;
-                rects.insert(
-                    (row_key, col_key),
-                    Rect {
-                        x,
-                        y,
-                        width,
-                        height,
-                    },
-                );
-            }
-        }
+        let keys = hints.alphabet.clone();
+        let (rows, columns) = orient(grid.rows, grid.columns, screen_width, screen_height);
+        let width = screen_width / columns;
+        let height = screen_height / rows;
+        let rect = |row: usize, col: usize| Rect {
+            x: width * col,
+            y: height * row,
+            width,
+            height,
+        };
+        let cells = match hints.labels {
+            LabelStyle::Grid => (0..rows)
+                .flat_map(|row| (0..columns).map(move |col| (row, col)))
+                .map(|(row, col)| (vec![keys[col], keys[row]], rect(row, col)))
+                .collect(),
+            LabelStyle::Shortest => shortest_labels(rows * columns, &keys)
+                .into_iter()
+                .enumerate()
+                .map(|(i, label)| (label, rect(i / columns, i % columns)))
+                .collect(),
+        };
         Self {
-            keycodes,
-            rects,
-            last_key: None,
+            keys,
+            cells,
+            typed: Vec::new(),
+            output,
+            final_rows: grid.final_rows,
+            final_cols: grid.final_columns,
         }
     }
 
-    pub fn handle_input(&mut self, keycode: u32) -> Option<Rect> {
-        dbg!(keycode);
-        if let Some(last) = self.last_key {
-            match self.rects.get(&(last, keycode)) {
-                Some(rect) => return Some(rect.clone()),
-                None => return None,
-            }
+    pub fn handle_input(&mut self, key: char) -> Option<Rect> {
+        let mut typed = self.typed.clone();
+        typed.push(key);
+        if let Some((_, rect)) = self.cells.iter().find(|(label, _)| *label == typed) {
+            return Some(rect.clone());
         }
-
-        if self.keycodes.contains(&keycode) {
-            self.last_key = Some(keycode);
+        if self
+            .cells
+            .iter()
+            .any(|(label, _)| label.starts_with(&typed))
+        {
+            self.typed = typed;
         }
         None
     }
@@ -162,95 +203,279 @@ impl InitialSelector {
         &self,
         buf: &mut [u8],
         screen_width: usize,
+        scale: f64,
         font_system: &mut FontSystem,
         swash_cache: &mut SwashCache,
-        keycode_symbols: &HashMap<u32, String>,
+        config: &Config,
     ) {
-        for (keypair, rect) in self.rects.iter() {
-            if let Some(key) = self.last_key {
-                if key != keypair.0 {
-                    continue;
-                }
-            }
-            //dbg!(rect);
-            let metrics = Metrics::new(14.0, 10.0);
-            let mut buffer = Buffer::new(font_system, metrics);
-            let mut buffer = buffer.borrow_with(font_system);
-            let attrs = Attrs::new();
-            buffer.set_text(
-                format!(
-                    "{}, {}",
-                    keycode_symbols.get(&keypair.0).unwrap(),
-                    keycode_symbols.get(&keypair.1).unwrap()
-                )
-                .as_str(),
-                &attrs,
-                Shaping::Advanced,
+        let attrs = Attrs::new().family(config.font.family());
+        let metrics = Metrics::new(
+            config.font.size * scale as f32,
+            config.font.line_height * scale as f32,
+        );
+        let thickness = scale_length(1, scale);
+        let padding = scale_length(5, scale);
+        let colors = &config.colors;
+        let typed_attrs = attrs.clone().color(text_color(colors.typed));
+        let rest_attrs = attrs
+            .clone()
+            .color(text_color(colors.text))
+            .weight(Weight::BOLD);
+        // Halfway to the background, the alpha of text colors is ignored
+        let faded_attrs = attrs
+            .clone()
+            .color(text_color(mix(colors.text, colors.background)));
+        // Labels can spill over into the next cell, so they are drawn in
+        // reading order to get the same picture every time
+        for (label, rect) in &self.cells {
+            draw_border(
+                buf,
+                rect.x,
+                rect.y,
+                rect.width,
+                rect.height,
+                screen_width,
+                thickness,
+                colors,
             );
-            //buffer.shape_until_scroll(true);
-            draw_border(buf, rect.x, rect.y, rect.width, rect.height, screen_width);
-            buffer.draw(
+            let text = join_keys(label);
+            let spans = if label.starts_with(&self.typed) {
+                // The typed keys along with the separator after them, labels
+                // that were typed completely have been selected already
+                let typed_len = match self.typed.len() {
+                    0 => 0,
+                    _ => join_keys(&self.typed).len() + SEPARATOR.len(),
+                };
+                let (typed, rest) = text.split_at(typed_len);
+                vec![(typed, typed_attrs.clone()), (rest, rest_attrs.clone())]
+            } else {
+                // Still shown so the grid doesn't look different while typing
+                vec![(text.as_str(), faded_attrs.clone())]
+            };
+            draw_spans(
+                buf,
+                screen_width,
+                font_system,
                 swash_cache,
-                Color::rgb(0x0, 0x0, 0x0),
-                |x, y, w, h, color| {
-                    let color = alpha_multiply(color.as_rgba_tuple());
-                    draw_rect(
-                        buf,
-                        x as usize + rect.x + 5,
-                        y as usize + rect.y + 5,
-                        w as usize,
-                        h as usize,
-                        screen_width,
-                        color,
-                    );
-                },
+                &spans,
+                rect.x + padding,
+                rect.y + padding,
+                metrics,
             );
         }
     }
 }
 
-#[derive(Debug)]
+fn mix(a: (u8, u8, u8, u8), b: (u8, u8, u8, u8)) -> (u8, u8, u8, u8) {
+    let mix = |a: u8, b: u8| ((a as u16 + b as u16) / 2) as u8;
+    (mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2), a.3)
+}
+
+const SEPARATOR: &str = ", ";
+
+fn join_keys(keys: &[char]) -> String {
+    keys.iter()
+        .map(char::to_string)
+        .collect::<Vec<_>>()
+        .join(SEPARATOR)
+}
+
+// Shown when there is more than one output: the first key picks the output,
+// after which the regular two-key grid of that output takes over.
+#[derive(Debug, Clone)]
+pub struct OutputSelector {
+    grids: Vec<(char, InitialSelector)>,
+}
+
+impl OutputSelector {
+    /// Fails when there are more grids than keys to pick them with.
+    pub fn new(keys: &[char], grids: Vec<InitialSelector>) -> Result<Self, String> {
+        if grids.len() > keys.len() {
+            return Err(format!(
+                "{} outputs but only {} hint characters to pick one with",
+                grids.len(),
+                keys.len()
+            ));
+        }
+        Ok(Self {
+            grids: keys.iter().copied().zip(grids).collect(),
+        })
+    }
+
+    pub fn handle_input(&mut self, pressed_key: char) -> Option<InitialSelector> {
+        let idx = self.grids.iter().position(|(key, _)| *key == pressed_key)?;
+        Some(self.grids.swap_remove(idx).1)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &self,
+        output: u32,
+        buf: &mut [u8],
+        screen_width: usize,
+        screen_height: usize,
+        scale: f64,
+        font_system: &mut FontSystem,
+        swash_cache: &mut SwashCache,
+        config: &Config,
+    ) {
+        let Some((key, grid)) = self.grids.iter().find(|(_, grid)| grid.output == output) else {
+            return;
+        };
+        grid.draw(buf, screen_width, scale, font_system, swash_cache, config);
+        draw_text(
+            buf,
+            screen_width,
+            font_system,
+            swash_cache,
+            &key.to_string(),
+            (screen_width / 2).saturating_sub(scale_length(50, scale)),
+            (screen_height / 2).saturating_sub(scale_length(100, scale)),
+            &Attrs::new().family(config.font.family()),
+            Metrics::new(200.0 * scale as f32, 200.0 * scale as f32),
+            config.colors.output_label,
+        );
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum SelectorState {
+    Output(OutputSelector),
     Initial(InitialSelector),
     Final(FinalSelector),
 }
 
 impl SelectorState {
+    /// Starts the selection on `grids`, one per output ordered left to
+    /// right. With more than one the first key picks the output, which
+    /// fails when there are more of them than characters in `alphabet`.
+    pub fn new(mut grids: Vec<InitialSelector>, alphabet: &[char]) -> Result<Self, String> {
+        if grids.len() == 1 {
+            Ok(SelectorState::Initial(grids.pop().unwrap()))
+        } else {
+            OutputSelector::new(alphabet, grids).map(SelectorState::Output)
+        }
+    }
+
+    /// The output the selection is happening on, or `None` while the user
+    /// still has to pick one.
+    pub fn output(&self) -> Option<u32> {
+        match self {
+            SelectorState::Output(_) => None,
+            SelectorState::Initial(selector) => Some(selector.output),
+            SelectorState::Final(selector) => Some(selector.output),
+        }
+    }
+
+    /// Fills the whole buffer of `output`: the background with the selection
+    /// on top, or transparent when another output was picked. Positions are
+    /// in buffer pixels, `scale` is the number of them per logical pixel.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &self,
+        output: u32,
+        buf: &mut [u8],
+        screen_width: usize,
+        screen_height: usize,
+        scale: f64,
+        font_system: &mut FontSystem,
+        swash_cache: &mut SwashCache,
+        config: &Config,
+    ) {
+        if self.output().is_some_and(|selected| selected != output) {
+            // Another output was picked, get out of the way on this one
+            buf.fill(0);
+            return;
+        }
+        let (r, g, b, a) = config.colors.background_pixel();
+        for pixel in buf.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[b, g, r, a]);
+        }
+        self.draw(
+            output,
+            buf,
+            screen_width,
+            screen_height,
+            scale,
+            font_system,
+            swash_cache,
+            config,
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn draw(
         &self,
+        output: u32,
         buf: &mut [u8],
         screen_width: usize,
+        screen_height: usize,
+        scale: f64,
         font_system: &mut FontSystem,
         swash_cache: &mut SwashCache,
-        keycode_symbols: &HashMap<u32, String>,
+        config: &Config,
     ) {
+        if self.output().is_some_and(|selected| selected != output) {
+            return;
+        }
         match self {
+            SelectorState::Output(selector) => selector.draw(
+                output,
+                buf,
+                screen_width,
+                screen_height,
+                scale,
+                font_system,
+                swash_cache,
+                config,
+            ),
             SelectorState::Initial(selector) => {
-                selector.draw(buf, screen_width, font_system, swash_cache, keycode_symbols)
-            }
-            SelectorState::Final(selector) => {
-                selector.draw(buf, screen_width, font_system, swash_cache, keycode_symbols)
+                selector.draw(buf, screen_width, scale, font_system, swash_cache, config)
             }
+            SelectorState::Final(selector) => selector.draw(buf, screen_width, scale, config),
         }
     }
 
-    pub fn handle_key(&mut self, key: u32) {
+    /// Returns whether the key moved the selection one level further.
+    pub fn handle_key(&mut self, key: char) -> bool {
         match self {
+            SelectorState::Output(output_selector) => {
+                let Some(selector) = output_selector.handle_input(key) else {
+                    return false;
+                };
+                *self = SelectorState::Initial(selector);
+                true
+            }
             SelectorState::Initial(initial_selector) => {
-                match initial_selector.handle_input(key) {
-                    Some(rect) => {
-                        *self = SelectorState::Final(FinalSelector::new(
-                            rect.x,
-                            rect.y,
-                            rect.width,
-                            rect.height,
-                            3,
-                            8,
-                            initial_selector.keycodes.clone(),
-                        ))
+                let typed = initial_selector.typed.len();
+                if let Some(rect) = initial_selector.handle_input(key) {
+                    // Lay the finer grid out along the longer side of the cell,
+                    // which is only taller than wide on portrait outputs
+                    let (mut rows, mut columns) =
+                        (initial_selector.final_rows, initial_selector.final_cols);
+                    if (rect.height > rect.width) != (rows > columns) {
+                        (rows, columns) = (columns, rows);
                     }
-                    None => {}
-                };
+                    let selector = FinalSelector::new(
+                        initial_selector.output,
+                        rect.x,
+                        rect.y,
+                        rect.width,
+                        rect.height,
+                        rows,
+                        columns,
+                        initial_selector.keys.clone(),
+                    );
+                    // A final grid of a single cell has nothing to pick, the
+                    // label already selected it
+                    *self = SelectorState::Final(if rows * columns <= 1 {
+                        selector.select(0, 0)
+                    } else {
+                        selector
+                    });
+                    return true;
+                }
+                initial_selector.typed.len() != typed
             }
             SelectorState::Final(final_selector) => final_selector.handle_input(key),
         }
@@ -1,3 +1,10 @@
+use cosmic_text::{Attrs, Buffer, Color, FontSystem, Metrics, Shaping, SwashCache, Weight};
+
+use crate::config::{ColorsConfig, Config};
+
+/// A straight `(r, g, b, a)` color.
+pub type Rgba = (u8, u8, u8, u8);
+
 pub fn alpha_blend(foreground: (u8, u8, u8, u8), background: (u8, u8, u8, u8)) -> (u8, u8, u8, u8) {
     let (r_f, g_f, b_f, a_f) = foreground;
     let (r_b, g_b, b_b, a_b) = background;
@@ -26,6 +33,12 @@ pub fn alpha_multiply(color: (u8, u8, u8, u8)) -> (u8, u8, u8, u8) {
     let b = (b as f32 * a_f) as u8;
     (r, g, b, a)
 }
+/// `length` logical pixels in buffer pixels, at least one.
+pub fn scale_length(length: usize, scale: f64) -> usize {
+    ((length as f64 * scale).round() as usize).max(1)
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn draw_border(
     buf: &mut [u8],
     x: usize,
@@ -33,15 +46,15 @@ pub fn draw_border(
     width: usize,
     height: usize,
     screen_width: usize,
+    thickness: usize,
+    colors: &ColorsConfig,
 ) {
-    for x in x..(x + width) {
-        set_pixel(buf, x, y, screen_width, (255, 255, 255, 255));
-        set_pixel(buf, x, y + height, screen_width, (255, 255, 255, 255));
-    }
-    for y in y..(y + height) {
-        set_pixel(buf, x, y, screen_width, (0, 0, 0, 255));
-        set_pixel(buf, x + width, y, screen_width, (0, 0, 0, 255));
-    }
+    let light = alpha_multiply(colors.border_light);
+    let dark = alpha_multiply(colors.border_dark);
+    draw_rect(buf, x, y, width, thickness, screen_width, light);
+    draw_rect(buf, x, y + height, width, thickness, screen_width, light);
+    draw_rect(buf, x, y, thickness, height, screen_width, dark);
+    draw_rect(buf, x + width, y, thickness, height, screen_width, dark);
 }
 
 pub fn draw_rect(
@@ -61,6 +74,10 @@ pub fn draw_rect(
 }
 
 pub fn set_pixel(buf: &mut [u8], x: usize, y: usize, screen_width: usize, color: (u8, u8, u8, u8)) {
+    // Borders and glyphs may stick out past the edges, clip them
+    if x >= screen_width || (y * screen_width + x + 1) * 4 > buf.len() {
+        return;
+    }
     let background_color = get_pixel(buf, x, y, screen_width);
     let color = alpha_blend(color, background_color);
     let (r, g, b, a) = color;
@@ -75,3 +92,106 @@ pub fn get_pixel(buf: &[u8], x: usize, y: usize, screen_width: usize) -> (u8, u8
     let i = (y * screen_width + x) * 4;
     (buf[i], buf[i + 1], buf[i + 2], buf[i + 3])
 }
+
+#[allow(clippy::too_many_arguments)]
+pub fn draw_text(
+    buf: &mut [u8],
+    screen_width: usize,
+    font_system: &mut FontSystem,
+    swash_cache: &mut SwashCache,
+    text: &str,
+    x: usize,
+    y: usize,
+    attrs: &Attrs,
+    metrics: Metrics,
+    color: (u8, u8, u8, u8),
+) {
+    draw_spans(
+        buf,
+        screen_width,
+        font_system,
+        swash_cache,
+        &[(text, attrs.clone().color(text_color(color)))],
+        x,
+        y,
+        metrics,
+    );
+}
+
+pub fn text_color((r, g, b, a): Rgba) -> Color {
+    Color::rgba(r, g, b, a)
+}
+
+/// Draws the texts of `spans` one after the other, each with its own
+/// attributes. Every span needs a color, see `text_color`, whose alpha is
+/// ignored.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_spans(
+    buf: &mut [u8],
+    screen_width: usize,
+    font_system: &mut FontSystem,
+    swash_cache: &mut SwashCache,
+    spans: &[(&str, Attrs)],
+    x: usize,
+    y: usize,
+    metrics: Metrics,
+) {
+    let mut buffer = Buffer::new(font_system, metrics);
+    let mut buffer = buffer.borrow_with(font_system);
+    let default_attrs = spans
+        .first()
+        .map_or_else(Attrs::new, |(_, attrs)| attrs.clone());
+    buffer.set_rich_text(
+        spans.iter().map(|(text, attrs)| (*text, attrs.clone())),
+        &default_attrs,
+        Shaping::Advanced,
+        None,
+    );
+    let default_color = Color::rgba(0, 0, 0, 0);
+    buffer.draw(
+        swash_cache,
+        default_color,
+        |glyph_x, glyph_y, w, h, color| {
+            // Glyphs taller than the line height start above `y`
+            let (Some(x), Some(y)) = (
+                x.checked_add_signed(glyph_x as isize),
+                y.checked_add_signed(glyph_y as isize),
+            ) else {
+                return;
+            };
+            let color = alpha_multiply(color.as_rgba_tuple());
+            draw_rect(buf, x, y, w as usize, h as usize, screen_width, color);
+        },
+    );
+}
+
+/// Draws `text` in the bottom left corner of the overlay, for what applies
+/// to the whole selection rather than a cell.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_status(
+    buf: &mut [u8],
+    screen_width: usize,
+    screen_height: usize,
+    scale: f64,
+    font_system: &mut FontSystem,
+    swash_cache: &mut SwashCache,
+    text: &str,
+    config: &Config,
+) {
+    let font_size = config.font.size * scale as f32;
+    let padding = scale_length(5, scale);
+    let attrs = Attrs::new()
+        .family(config.font.family())
+        .color(text_color(config.colors.typed))
+        .weight(Weight::BOLD);
+    draw_spans(
+        buf,
+        screen_width,
+        font_system,
+        swash_cache,
+        &[(text, attrs)],
+        padding,
+        screen_height.saturating_sub(padding + font_size.ceil() as usize),
+        Metrics::new(font_size, config.font.line_height * scale as f32),
+    );
+}
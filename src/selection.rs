@@ -0,0 +1,308 @@
+use xkbcommon::xkb::Keysym;
+
+use crate::{
+    config::{Config, MarksConfig},
+    keyboard_utils::{Key, Modifiers},
+    position_selector::SelectorState,
+};
+
+/// The most clicks a count can ask for, so a typo can't keep the pointer
+/// busy for minutes.
+pub const MAX_COUNT: u32 = 100;
+
+/// What the character typed after one of the `marks` keys is for.
+#[derive(Debug, Clone, Copy)]
+enum MarkCommand {
+    Set,
+    Jump,
+}
+
+/// What a key typed while selecting asks the session to do.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Response {
+    /// Nothing that is shown changed
+    None,
+    /// The overlay has to be drawn again
+    Redraw,
+    Cancel,
+    SetMark(char),
+    JumpToMark(char),
+}
+
+/// A position being selected with the keyboard, along with the count and
+/// the modifiers to hold that were typed on the way.
+#[derive(Debug)]
+pub struct Selection {
+    pub selector: SelectorState,
+    /// Earlier states of `selector`, for stepping back with backspace
+    history: Vec<SelectorState>,
+    /// How many times to click, typed as digits before the first label. 0
+    /// when none was typed
+    pub count: u32,
+    mark_keys: MarksConfig,
+    /// Set after typing one of `mark_keys`, until the name of the mark
+    pending_mark: Option<MarkCommand>,
+    /// The global position and output of the mark that was jumped to, which
+    /// stands in for a selection made with the grid
+    pub mark_selection: Option<((i32, i32), u32)>,
+    /// The modifiers held while typing the last label, which pick the action
+    pub last_key_modifiers: Modifiers,
+    /// Modifiers to hold during the click, toggled by tapping their keys
+    pub held_modifiers: Modifiers,
+    /// The evdev keycode and modifier of a modifier key that was pressed
+    /// without any other key after it, which toggles it when released
+    tapped_modifier: Option<(u32, Modifiers)>,
+}
+
+impl Selection {
+    pub fn new(selector: SelectorState, config: &Config) -> Self {
+        Self {
+            selector,
+            history: Vec::new(),
+            count: 0,
+            mark_keys: config.marks.clone(),
+            pending_mark: None,
+            mark_selection: None,
+            last_key_modifiers: Modifiers::default(),
+            held_modifiers: Modifiers::default(),
+            tapped_modifier: None,
+        }
+    }
+
+    /// Starts over from the first level of `selector`, keeping the count
+    /// and the held modifiers.
+    pub fn reset(&mut self, selector: SelectorState) {
+        self.selector = selector;
+        self.history.clear();
+        self.mark_selection = None;
+    }
+
+    pub fn handle_key(&mut self, key: &Key) -> Response {
+        // Tapping a modifier key on its own toggles holding it during the
+        // click, holding it while typing a label still picks the action
+        // instead
+        if !key.pressed {
+            return match self.tapped_modifier {
+                Some((tapped, modifier)) if tapped == key.code => {
+                    self.tapped_modifier = None;
+                    self.held_modifiers.toggle(modifier);
+                    Response::Redraw
+                }
+                _ => Response::None,
+            };
+        }
+        self.tapped_modifier = key
+            .keysym
+            .and_then(Modifiers::of_key)
+            .map(|modifier| (key.code, modifier));
+        match key.keysym {
+            Some(Keysym::Escape) => Response::Cancel,
+            Some(Keysym::BackSpace) => self.step_back(),
+            _ => match key.key_char {
+                Some(c) => {
+                    self.last_key_modifiers = key.modifiers;
+                    self.handle_char(c, key.typed)
+                }
+                None => Response::None,
+            },
+        }
+    }
+
+    /// Handles a typed character, one of the `mark_keys` and the mark name
+    /// after it or otherwise a label. Marks are matched by what was actually
+    /// `typed`, so e.g. shift+m is `M`, labels see `handle_label`.
+    fn handle_char(&mut self, key: char, typed: Option<char>) -> Response {
+        let typed_or_key = typed.unwrap_or(key);
+        match self.pending_mark.take() {
+            Some(MarkCommand::Set) => Response::SetMark(typed_or_key),
+            Some(MarkCommand::Jump) => Response::JumpToMark(typed_or_key),
+            None if typed_or_key == self.mark_keys.set => {
+                self.pending_mark = Some(MarkCommand::Set);
+                Response::None
+            }
+            None if typed_or_key == self.mark_keys.jump => {
+                self.pending_mark = Some(MarkCommand::Jump);
+                Response::None
+            }
+            None => self.handle_label(key, typed),
+        }
+    }
+
+    /// Matches what was `typed` against the labels first, for labels that
+    /// are on a higher level of the layout like `.` on AZERTY, then the
+    /// `key` without modifiers, so labels can still be typed while a
+    /// modifier is held to pick the action.
+    fn handle_label(&mut self, key: char, typed: Option<char>) -> Response {
+        let previous = self.selector.clone();
+        let typed = typed.filter(|&typed| typed != key);
+        if let Some(typed) = typed
+            && self.selector.handle_key(typed)
+        {
+            // Shift was needed to type the label, it doesn't pick the action
+            self.last_key_modifiers.shift = false;
+            self.history.push(previous);
+            return Response::Redraw;
+        }
+        if self.selector.handle_key(key) {
+            self.history.push(previous);
+            return Response::Redraw;
+        }
+        if self.history.is_empty()
+            && let Some(digit) = typed
+                .and_then(|typed| typed.to_digit(10))
+                .or(key.to_digit(10))
+        {
+            // Digits that aren't labels are a count, but only before the
+            // first label
+            self.count = (self.count * 10 + digit).min(MAX_COUNT);
+        }
+        Response::None
+    }
+
+    /// Undoes the last key that moved the selection a level further, or the
+    /// last digit of the count before that.
+    fn step_back(&mut self) -> Response {
+        if self.pending_mark.take().is_some() {
+            return Response::None;
+        }
+        match self.history.pop() {
+            Some(previous) => {
+                self.selector = previous;
+                Response::Redraw
+            }
+            None => {
+                self.count /= 10;
+                Response::None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keyboard_utils::Keyboard, position_selector::InitialSelector};
+
+    // Evdev keycodes, named after the keys of a US layout
+    const KEY_1: u32 = 2;
+    const KEY_2: u32 = 3;
+    const KEY_BACKSPACE: u32 = 14;
+    const KEY_Q: u32 = 16;
+    const KEY_SEMICOLON: u32 = 39;
+    const KEY_COMMA: u32 = 51;
+    const KEY_DOT: u32 = 52;
+    const KEY_SLASH: u32 = 53;
+
+    const SHIFT: Modifiers = Modifiers {
+        shift: true,
+        ctrl: false,
+        alt: false,
+        logo: false,
+    };
+
+    fn selection(config: &Config) -> Selection {
+        let grid = InitialSelector::new(0, &config.hints, &config.grid, 1920, 1080);
+        Selection::new(SelectorState::Initial(grid), config)
+    }
+
+    fn press(selection: &mut Selection, keyboard: &Keyboard, code: u32) -> Response {
+        let response = selection.handle_key(&keyboard.key(code, true));
+        selection.handle_key(&keyboard.key(code, false));
+        response
+    }
+
+    /// The selector after typing `labels` on a US layout.
+    fn typed(config: &Config, labels: &[u32]) -> String {
+        let keyboard = Keyboard::with_layout("us");
+        let mut selection = selection(config);
+        for &code in labels {
+            press(&mut selection, &keyboard, code);
+        }
+        format!("{:?}", selection.selector)
+    }
+
+    /// A selection at the final grid, whose labels are the whole alphabet.
+    fn final_grid(config: &Config) -> Selection {
+        let keyboard = Keyboard::with_layout("us");
+        let mut selection = selection(config);
+        press(&mut selection, &keyboard, KEY_Q);
+        press(&mut selection, &keyboard, KEY_Q);
+        assert!(matches!(selection.selector, SelectorState::Final(_)));
+        selection
+    }
+
+    #[test]
+    fn labels_on_higher_levels() {
+        let config = Config::default();
+        let mut keyboard = Keyboard::with_layout("fr");
+        keyboard.hold(SHIFT);
+        // . and / of the default alphabet need shift on AZERTY
+        for (code, us_code) in [(KEY_COMMA, KEY_DOT), (KEY_DOT, KEY_SLASH)] {
+            let mut selection = final_grid(&config);
+            assert_eq!(press(&mut selection, &keyboard, code), Response::Redraw);
+            assert_eq!(
+                format!("{:?}", selection.selector),
+                typed(&config, &[KEY_Q, KEY_Q, us_code])
+            );
+            assert!(!selection.last_key_modifiers.shift);
+        }
+    }
+
+    #[test]
+    fn shift_picks_the_action_when_not_needed_for_the_label() {
+        let config = Config::default();
+        let mut keyboard = Keyboard::with_layout("us");
+        keyboard.hold(SHIFT);
+        let mut selection = final_grid(&config);
+        // Types :, which isn't a label, unlike ;
+        assert_eq!(
+            press(&mut selection, &keyboard, KEY_SEMICOLON),
+            Response::Redraw
+        );
+        assert_eq!(
+            format!("{:?}", selection.selector),
+            typed(&config, &[KEY_Q, KEY_Q, KEY_SEMICOLON])
+        );
+        assert!(selection.last_key_modifiers.shift);
+    }
+
+    #[test]
+    fn count_before_the_first_label() {
+        let config = Config::default();
+        let keyboard = Keyboard::with_layout("us");
+        let mut selection = selection(&config);
+        for code in [KEY_1, KEY_2] {
+            assert_eq!(press(&mut selection, &keyboard, code), Response::None);
+        }
+        assert_eq!(selection.count, 12);
+        // Backspace drops the last digit
+        assert_eq!(
+            press(&mut selection, &keyboard, KEY_BACKSPACE),
+            Response::None
+        );
+        assert_eq!(selection.count, 1);
+        for _ in 0..3 {
+            press(&mut selection, &keyboard, KEY_2);
+        }
+        assert_eq!(selection.count, MAX_COUNT);
+        // Digits after a label aren't a count
+        let mut selection = self::selection(&config);
+        press(&mut selection, &keyboard, KEY_Q);
+        press(&mut selection, &keyboard, KEY_2);
+        assert_eq!(selection.count, 0);
+    }
+
+    #[test]
+    fn azerty_count() {
+        let config = Config::default();
+        let mut keyboard = Keyboard::with_layout("fr");
+        let mut selection = selection(&config);
+        // & and é without shift
+        press(&mut selection, &keyboard, KEY_1);
+        assert_eq!(selection.count, 0);
+        keyboard.hold(SHIFT);
+        press(&mut selection, &keyboard, KEY_1);
+        press(&mut selection, &keyboard, KEY_2);
+        assert_eq!(selection.count, 12);
+    }
+}
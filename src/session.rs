@@ -0,0 +1,497 @@
+use cosmic_text::{FontSystem, SwashCache};
+use nix::poll::PollTimeout;
+use smithay_client_toolkit::seat::pointer::BTN_LEFT;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use wayland_client::protocol::wl_pointer::ButtonState;
+use xkbcommon::xkb::Keysym;
+
+use crate::{
+    actions::interpolate,
+    backend::{Backend, OutputInfo},
+    cli::{Args, EXIT_CANCELLED, EXIT_ERROR, Mode, format_selection},
+    config::{Config, ScrollConfig},
+    keyboard_utils::{Key, KeyboardEvent},
+    normal_mode::{DEFAULT_REPEAT_INFO, NormalMode},
+    pointer::PointerBackend,
+    position_selector::SelectorState,
+    render_utils::draw_status,
+    selection::{Response, Selection},
+    state::{LastClick, Marks},
+};
+
+/// Distance in pixels between the motion events of a drag.
+const DRAG_STEP: u32 = 20;
+
+/// Time between the motion events of a drag, so applications see the
+/// pointer moving instead of jumping to the end.
+const DRAG_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Time between the clicks of a count, for the application to catch up.
+const REPEAT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How a selection ended, so it can be reported to whoever asked for it.
+#[derive(Debug, Default)]
+pub struct Outcome {
+    /// Exit status, see the usage text
+    pub status: i32,
+    /// What `--print` and `--region` print
+    pub stdout: String,
+}
+
+/// One selection, from showing the overlay until the action was performed
+/// or it was cancelled.
+pub struct Session {
+    args: Args,
+    config: Config,
+    /// Connected once for the whole session, handed back to the backend by
+    /// `end`
+    pointer: Box<dyn PointerBackend>,
+    selection: Selection,
+    /// Set while in scroll mode, keys scroll instead of selecting
+    scroll: Option<ScrollConfig>,
+    /// Set in normal mode, keys move the pointer instead of selecting
+    normal: Option<NormalMode>,
+    cancelled: bool,
+    /// Like cancelled, but exits with an error
+    failed: bool,
+    finished: bool,
+    drag_start: Option<(i32, i32)>,
+    region_start: Option<((i32, i32), u32)>,
+}
+
+impl Session {
+    pub fn start(backend: &mut dyn Backend, args: Args, config: Config) -> Result<Self, String> {
+        let pointer = backend.take_pointer(config.backend)?;
+        let target = args.output.as_ref().unwrap_or(&config.output);
+        if let Err(err) = backend.show(target) {
+            backend.keep_pointer(config.backend, pointer);
+            return Err(err);
+        }
+        let selector = match backend.create_selector(&config) {
+            Ok(selector) => selector,
+            Err(err) => {
+                backend.hide();
+                backend.keep_pointer(config.backend, pointer);
+                return Err(err);
+            }
+        };
+        // Left over from a previous session of the daemon
+        backend.take_keyboard_events();
+        backend.take_layout_changed();
+        backend.request_redraw();
+        Ok(Self {
+            selection: Selection::new(selector, &config),
+            args,
+            config,
+            pointer,
+            scroll: None,
+            normal: None,
+            cancelled: false,
+            failed: false,
+            finished: false,
+            drag_start: None,
+            region_start: None,
+        })
+    }
+
+    /// Hands the pointer back to `backend`, for the next session to use.
+    pub fn end(self, backend: &mut dyn Backend) {
+        backend.keep_pointer(self.config.backend, self.pointer);
+    }
+
+    /// Cancels the selection with the next `update`.
+    pub fn cancel(&mut self) {
+        self.cancelled = true;
+    }
+
+    /// When the selection needs updating even without any events, to keep
+    /// the pointer moving while a key is held in normal mode.
+    pub fn next_update(&self) -> Option<Instant> {
+        self.normal.as_ref().and_then(NormalMode::next_step)
+    }
+
+    /// Draws the current state of the selection and acts on it once it is
+    /// complete. Returns the outcome when the session is over, the overlay
+    /// has been hidden by then.
+    pub fn update(
+        &mut self,
+        backend: &mut dyn Backend,
+        font_system: &mut FontSystem,
+        swash_cache: &mut SwashCache,
+    ) -> Option<Outcome> {
+        for event in backend.take_keyboard_events() {
+            self.handle_keyboard_event(backend, event);
+        }
+        let config = &self.config;
+        let pointer = &mut *self.pointer;
+        loop {
+            // Every output may have gone away
+            if self.cancelled || self.failed || !backend.is_shown() {
+                if self.drag_start.is_some() {
+                    // The pointer is still at the start, so this doesn't drop anything elsewhere
+                    pointer.button(BTN_LEFT, ButtonState::Released);
+                    pointer.frame();
+                    backend.release_held_modifiers(self.selection.held_modifiers);
+                }
+                backend.hide();
+                return Some(Outcome {
+                    status: if self.failed {
+                        EXIT_ERROR
+                    } else {
+                        EXIT_CANCELLED
+                    },
+                    ..Default::default()
+                });
+            }
+            if backend.take_layout_changed() {
+                // The grid was laid out for other outputs or buffer sizes
+                if !reset_selection(&mut self.selection, backend, config) {
+                    self.failed = true;
+                    continue;
+                }
+                backend.request_redraw();
+            }
+            if let Some(normal) = self.normal.as_mut() {
+                normal.tick(Instant::now(), pointer);
+            }
+            let selection = self
+                .selection
+                .mark_selection
+                .or(match &self.selection.selector {
+                    SelectorState::Final(selector) if selector.depth == 1 => backend
+                        .global_position(selector)
+                        .map(|position| (position, selector.output)),
+                    _ => None,
+                });
+            if let Some((position, output)) = selection {
+                let extents = backend.layout_extents();
+                let held_modifiers = self.selection.held_modifiers;
+                match self.args.mode {
+                    Mode::Drag if self.drag_start.is_none() => {
+                        // Hold the button at the first position and select the second one
+                        backend.press_held_modifiers(held_modifiers);
+                        pointer.motion_absolute(position, extents);
+                        pointer.frame();
+                        pointer.button(BTN_LEFT, ButtonState::Pressed);
+                        pointer.frame();
+                        self.drag_start = Some(position);
+                        self.failed = !reset_selection(&mut self.selection, backend, config);
+                        backend.request_redraw();
+                        continue;
+                    }
+                    Mode::Region if self.region_start.is_none() => {
+                        self.region_start = Some((position, output));
+                        self.failed = !reset_selection(&mut self.selection, backend, config);
+                        backend.request_redraw();
+                        continue;
+                    }
+                    Mode::Scroll if self.scroll.is_none() && !self.finished => {
+                        pointer.motion_absolute(position, extents);
+                        pointer.frame();
+                        self.scroll = Some(config.scroll.clone());
+                        backend.request_redraw();
+                        continue;
+                    }
+                    // Keep the keyboard until escape is pressed
+                    Mode::Scroll if !self.finished => {}
+                    Mode::Normal if self.normal.is_none() && !self.finished => {
+                        pointer.motion_absolute(position, extents);
+                        pointer.frame();
+                        self.normal = Some(NormalMode::new(
+                            config.normal.clone(),
+                            backend.repeat_info().unwrap_or(DEFAULT_REPEAT_INFO),
+                            position,
+                            extents,
+                        ));
+                        // Out of the way of the pointer
+                        backend.request_redraw();
+                        continue;
+                    }
+                    // Keep the keyboard until a click key is pressed
+                    Mode::Normal if !self.finished => {}
+                    Mode::Print | Mode::Region => {
+                        let ((x, y), output) = self.region_start.unwrap_or((position, output));
+                        let (end_x, end_y) = position;
+                        // The output may have been unplugged since the first position
+                        let output_name = backend
+                            .outputs()
+                            .into_iter()
+                            .find(|info| info.id == output)
+                            .and_then(|info| info.name)
+                            .unwrap_or_default();
+                        backend.hide();
+                        let selection = format_selection(
+                            self.args.format(),
+                            (x.min(end_x), y.min(end_y)),
+                            (x.abs_diff(end_x), y.abs_diff(end_y)),
+                            &output_name,
+                        );
+                        return Some(Outcome {
+                            stdout: format!("{selection}\n"),
+                            ..Default::default()
+                        });
+                    }
+                    _ => {
+                        backend.hide();
+                        if self.drag_start.is_none() {
+                            backend.press_held_modifiers(held_modifiers);
+                        }
+                        if let Some(start) = self.drag_start {
+                            for position in interpolate(start, position, DRAG_STEP) {
+                                pointer.motion_absolute(position, extents);
+                                pointer.frame();
+                                backend.flush();
+                                thread::sleep(DRAG_INTERVAL);
+                            }
+                            pointer.button(BTN_LEFT, ButtonState::Released);
+                            pointer.frame();
+                            backend.release_held_modifiers(held_modifiers);
+                        } else if matches!(self.args.mode, Mode::Click | Mode::Normal) {
+                            let action = config
+                                .click
+                                .modifier_action(self.selection.last_key_modifiers)
+                                .or(self.args.action)
+                                .unwrap_or(config.click.action);
+                            let position = match &self.normal {
+                                // Already moved there with the keys
+                                Some(normal) => normal.position(),
+                                None => {
+                                    pointer.motion_absolute(position, extents);
+                                    pointer.frame();
+                                    position
+                                }
+                            };
+                            for i in 0..self.selection.count.max(1) {
+                                if i > 0 {
+                                    backend.flush();
+                                    thread::sleep(REPEAT_INTERVAL);
+                                }
+                                action.perform(pointer);
+                            }
+                            backend.release_held_modifiers(held_modifiers);
+                            let last = LastClick { position, action };
+                            if let Err(err) = last.save() {
+                                eprintln!("nomouse: can't save the last click: {err}");
+                            }
+                        }
+                        backend.sync();
+                        if let Some(text) = &self.args.text
+                            && let Err(err) = backend.type_text(text)
+                        {
+                            eprintln!("nomouse: can't type {text:?}: {err}");
+                            return Some(Outcome {
+                                status: EXIT_ERROR,
+                                ..Default::default()
+                            });
+                        }
+                        return Some(Outcome::default());
+                    }
+                }
+            }
+            self.draw(backend, font_system, swash_cache);
+            return None;
+        }
+    }
+
+    fn handle_keyboard_event(&mut self, backend: &mut dyn Backend, event: KeyboardEvent) {
+        match event {
+            KeyboardEvent::Key(key) => self.handle_key(backend, &key),
+            KeyboardEvent::Modifiers(modifiers) => {
+                if let Some(normal) = self.normal.as_mut() {
+                    normal.slow = modifiers.shift;
+                }
+            }
+            KeyboardEvent::Leave => {
+                // No release events for keys held while leaving
+                if let Some(normal) = self.normal.as_mut() {
+                    normal.release_all();
+                }
+            }
+        }
+    }
+
+    fn handle_key(&mut self, backend: &mut dyn Backend, key: &Key) {
+        let escape = key.keysym == Some(Keysym::Escape);
+        if let Some(normal) = self.normal.as_mut() {
+            if !key.pressed {
+                normal.release(key.code);
+            } else if escape {
+                self.cancelled = true;
+            } else if matches!(
+                key.keysym,
+                Some(Keysym::space | Keysym::Return | Keysym::KP_Enter)
+            ) {
+                self.selection.last_key_modifiers = key.modifiers;
+                self.finished = true;
+            } else if let Some(c) = key.key_char
+                && let Some(direction) = normal.direction_for(c)
+            {
+                normal.press(key.code, direction, Instant::now(), &mut *self.pointer);
+            }
+            return;
+        }
+        if let Some(scroll) = self.scroll.as_ref() {
+            if !key.pressed {
+                return;
+            }
+            if escape {
+                self.scroll = None;
+                self.finished = true;
+            } else if let Some(c) = key.key_char
+                && let Some((axis, direction)) = scroll.axis_for(c)
+            {
+                self.pointer.scroll(axis, direction, scroll.step);
+                self.pointer.frame();
+            }
+            return;
+        }
+        match self.selection.handle_key(key) {
+            Response::None => {}
+            Response::Redraw => backend.request_redraw(),
+            Response::Cancel => self.cancelled = true,
+            Response::SetMark(mark) => self.set_mark(backend, mark),
+            Response::JumpToMark(mark) => self.jump_to_mark(backend, mark),
+        }
+    }
+
+    /// Saves the center of the current cell as `mark` of its output.
+    fn set_mark(&self, backend: &dyn Backend, mark: char) {
+        let SelectorState::Final(selector) = &self.selection.selector else {
+            return;
+        };
+        let outputs = backend.outputs();
+        let Some(output) = outputs.iter().find(|info| info.id == selector.output) else {
+            return;
+        };
+        let Some(name) = &output.name else {
+            eprintln!("nomouse: the output has no name, can't set a mark on it");
+            return;
+        };
+        let Some((x, y)) = backend.global_position(selector) else {
+            return;
+        };
+        let position = (x - output.rect.0, y - output.rect.1);
+        let result = Marks::load().and_then(|mut marks| {
+            marks.set(name, mark, position)?;
+            marks.save()
+        });
+        if let Err(err) = result {
+            eprintln!("nomouse: can't save mark {mark:?}: {err}");
+        }
+    }
+
+    /// Selects `mark` on the picked output, or on the first output with that
+    /// mark from the left when none was picked yet.
+    fn jump_to_mark(&mut self, backend: &dyn Backend, mark: char) {
+        let marks = match Marks::load() {
+            Ok(marks) => marks,
+            Err(err) => {
+                eprintln!("nomouse: can't load marks: {err}");
+                return;
+            }
+        };
+        let picked = self.selection.selector.output();
+        let mut outputs: Vec<OutputInfo> = backend
+            .outputs()
+            .into_iter()
+            .filter(|output| output.shown && picked.is_none_or(|picked| picked == output.id))
+            .collect();
+        outputs.sort_by_key(|output| (output.rect.0, output.rect.1));
+        let found = outputs.into_iter().find_map(|output| {
+            let (x, y) = marks.get(output.name.as_deref()?, mark)?;
+            Some(((output.rect.0 + x, output.rect.1 + y), output.id))
+        });
+        match found {
+            Some(selection) => self.selection.mark_selection = Some(selection),
+            None => eprintln!("nomouse: no mark {mark:?} on the shown outputs"),
+        }
+    }
+
+    /// Draws the overlays that changed since they were last drawn.
+    fn draw(
+        &self,
+        backend: &mut dyn Backend,
+        font_system: &mut FontSystem,
+        swash_cache: &mut SwashCache,
+    ) {
+        if self.scroll.is_some() || self.normal.is_some() {
+            // Only kept around for the keyboard focus while scrolling or
+            // moving the pointer
+            backend.draw(None);
+            return;
+        }
+        let config = &self.config;
+        let selector = &self.selection.selector;
+        let held_modifiers = self.selection.held_modifiers;
+        backend.draw(Some(&mut |output, framebuf, width, height, scale| {
+            selector.render(
+                output,
+                framebuf,
+                width,
+                height,
+                scale,
+                font_system,
+                swash_cache,
+                config,
+            );
+            if !held_modifiers.is_empty() {
+                draw_status(
+                    framebuf,
+                    width,
+                    height,
+                    scale,
+                    font_system,
+                    swash_cache,
+                    &format!("hold {held_modifiers}"),
+                    config,
+                );
+            }
+        }));
+    }
+}
+
+/// Starts the selection over with a new grid. Reports and returns false when
+/// there can't be one for the current outputs.
+fn reset_selection(selection: &mut Selection, backend: &dyn Backend, config: &Config) -> bool {
+    match backend.create_selector(config) {
+        Ok(selector) => {
+            selection.reset(selector);
+            true
+        }
+        Err(err) => {
+            eprintln!("nomouse: {err}");
+            false
+        }
+    }
+}
+
+/// How long to poll for events until `deadline`, forever without one.
+pub fn poll_timeout(deadline: Option<Instant>) -> PollTimeout {
+    let Some(deadline) = deadline else {
+        return PollTimeout::NONE;
+    };
+    let timeout = deadline.saturating_duration_since(Instant::now());
+    // Rounded up, waking up early would only mean polling again
+    PollTimeout::try_from(timeout + Duration::from_micros(999)).unwrap_or(PollTimeout::MAX)
+}
+
+/// Clicks where the last click of a selection was, with the same action,
+/// without showing the overlay.
+pub fn repeat_last(backend: &mut dyn Backend, config: &Config) -> Result<Outcome, String> {
+    let LastClick { position, action } = LastClick::load()?;
+    let (x, y) = position;
+    if !backend.outputs().iter().any(|output| output.contains(x, y)) {
+        return Err(format!("the last click at {x},{y} isn't on any output"));
+    }
+    let mut pointer = backend.take_pointer(config.backend)?;
+    pointer.motion_absolute(position, backend.layout_extents());
+    pointer.frame();
+    action.perform(&mut *pointer);
+    backend.keep_pointer(config.backend, pointer);
+    backend.sync();
+    Ok(Outcome::default())
+}
@@ -0,0 +1,221 @@
+// What nomouse remembers between runs, kept in `$XDG_STATE_HOME/nomouse/`.
+
+use std::{collections::BTreeMap, fmt, fs, io, path::PathBuf};
+
+use crate::actions::Action;
+
+pub fn state_dir() -> Option<PathBuf> {
+    let state_home = std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })?;
+    Some(state_home.join("nomouse"))
+}
+
+/// Writes `contents` to `name` in the state directory, creating it first.
+fn write(name: &str, contents: &str) -> Result<(), String> {
+    let dir = state_dir().ok_or("neither XDG_STATE_HOME nor HOME is set")?;
+    fs::create_dir_all(&dir).map_err(|err| format!("{}: {err}", dir.display()))?;
+    let path = dir.join(name);
+    fs::write(&path, contents).map_err(|err| format!("{}: {err}", path.display()))
+}
+
+/// Reads `name` from the state directory, `None` when it doesn't exist yet.
+fn read(name: &str) -> Result<Option<String>, String> {
+    let Some(path) = state_dir().map(|dir| dir.join(name)) else {
+        return Ok(None);
+    };
+    match fs::read_to_string(&path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(format!("{}: {err}", path.display())),
+    }
+}
+
+/// The remote desktop portal's token for skipping its permission dialog.
+const RESTORE_TOKEN_FILE: &str = "restore-token";
+
+pub fn load_restore_token() -> Result<Option<String>, String> {
+    Ok(read(RESTORE_TOKEN_FILE)?.map(|token| token.trim().to_string()))
+}
+
+/// Tokens are only good once, the portal hands out a new one every time.
+pub fn save_restore_token(token: &str) -> Result<(), String> {
+    write(RESTORE_TOKEN_FILE, &format!("{token}\n"))
+}
+
+/// The last click, for `--repeat-last`. Stored as `X Y ACTION` on one line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LastClick {
+    /// In global compositor coordinates
+    pub position: (i32, i32),
+    pub action: Action,
+}
+
+impl LastClick {
+    const FILE: &str = "last";
+
+    pub fn load() -> Result<Self, String> {
+        Self::parse(&read(Self::FILE)?.ok_or("nothing to repeat yet")?)
+    }
+
+    fn parse(contents: &str) -> Result<Self, String> {
+        let mut fields = contents.split_whitespace();
+        let mut next = || fields.next().unwrap_or_default();
+        let (x, y, action) = (next(), next(), next());
+        (|| {
+            Some(Self {
+                position: (x.parse().ok()?, y.parse().ok()?),
+                action: Action::parse(action)?,
+            })
+        })()
+        .ok_or_else(|| format!("invalid last click {:?}", contents.trim()))
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        write(Self::FILE, &self.to_string())
+    }
+}
+
+impl fmt::Display for LastClick {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (x, y) = self.position;
+        writeln!(f, "{x} {y} {}", self.action.name())
+    }
+}
+
+/// Positions saved with the `marks.set` key, by output name and mark name.
+/// Positions are relative to the top left corner of the output, so marks
+/// stay put when outputs are rearranged. Stored as `MARK X Y OUTPUT` lines.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Marks {
+    marks: BTreeMap<(String, char), (i32, i32)>,
+}
+
+impl Marks {
+    const FILE: &str = "marks";
+
+    pub fn load() -> Result<Self, String> {
+        Ok(Self::parse(&read(Self::FILE)?.unwrap_or_default()))
+    }
+
+    /// Reads the lines of the marks file. Lines that aren't marks are
+    /// skipped with a warning, so one bad line doesn't lose all marks.
+    fn parse(contents: &str) -> Self {
+        let mut marks = BTreeMap::new();
+        for line in contents.lines() {
+            let mut fields = line.splitn(4, ' ');
+            let mut next = || fields.next().unwrap_or_default();
+            let (mark, x, y, output) = (next(), next(), next(), next());
+            let mut chars = mark.chars();
+            let parsed = (|| {
+                let (Some(mark), None) = (chars.next(), chars.next()) else {
+                    return None;
+                };
+                Some((
+                    (output.to_string(), mark),
+                    (x.parse().ok()?, y.parse().ok()?),
+                ))
+            })();
+            match parsed {
+                Some((key, position)) if !output.is_empty() => {
+                    marks.insert(key, position);
+                }
+                _ => eprintln!("nomouse: skipping invalid mark {line:?}"),
+            }
+        }
+        Self { marks }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        write(Self::FILE, &self.to_string())
+    }
+
+    pub fn get(&self, output: &str, mark: char) -> Option<(i32, i32)> {
+        self.marks.get(&(output.to_string(), mark)).copied()
+    }
+
+    /// Fails for whitespace, which can't be told apart from the separators
+    /// in the file.
+    pub fn set(&mut self, output: &str, mark: char, position: (i32, i32)) -> Result<(), String> {
+        if mark.is_whitespace() {
+            return Err("marks can't be named by whitespace".into());
+        }
+        self.marks.insert((output.to_string(), mark), position);
+        Ok(())
+    }
+}
+
+impl fmt::Display for Marks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for ((output, mark), (x, y)) in &self.marks {
+            writeln!(f, "{mark} {x} {y} {output}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LastClick, Marks};
+    use crate::actions::Action;
+
+    #[test]
+    fn last_click_round_trip() {
+        for action in [Action::Left, Action::Right, Action::Double, Action::None] {
+            let last = LastClick {
+                position: (-1920, 1079),
+                action,
+            };
+            assert_eq!(LastClick::parse(&last.to_string()), Ok(last));
+        }
+        assert_eq!(
+            LastClick {
+                position: (10, 20),
+                action: Action::Middle
+            }
+            .to_string(),
+            "10 20 middle\n"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_last_clicks() {
+        for contents in ["", "10 20", "10 x left", "10 20 sideways", "1.5 2 left"] {
+            assert!(LastClick::parse(contents).is_err(), "{contents:?}");
+        }
+    }
+
+    #[test]
+    fn marks_round_trip() {
+        let mut marks = Marks::default();
+        marks.set("DP-1", 'a', (10, 20)).unwrap();
+        marks.set("DP-1", 'A', (-5, 0)).unwrap();
+        // Output names may contain spaces, they come last
+        marks.set("Dell Inc. U2720Q", 'ä', (3840, 2160)).unwrap();
+        let saved = marks.to_string();
+        assert_eq!(
+            saved,
+            "A -5 0 DP-1\na 10 20 DP-1\nä 3840 2160 Dell Inc. U2720Q\n"
+        );
+        assert_eq!(Marks::parse(&saved), marks);
+    }
+
+    #[test]
+    fn skips_invalid_marks() {
+        let marks = Marks::parse("a 1 2 DP-1\nab 1 2 DP-1\nb x 2 DP-1\nc 1 2\n\nd 3 4 HDMI-A-1\n");
+        assert_eq!(marks.get("DP-1", 'a'), Some((1, 2)));
+        assert_eq!(marks.get("HDMI-A-1", 'd'), Some((3, 4)));
+        assert_eq!(marks.marks.len(), 2);
+    }
+
+    #[test]
+    fn rejects_whitespace_marks() {
+        let mut marks = Marks::default();
+        assert!(marks.set("DP-1", ' ', (1, 2)).is_err());
+        assert!(marks.set("DP-1", '\t', (1, 2)).is_err());
+        assert_eq!(marks, Marks::default());
+    }
+}
@@ -0,0 +1,136 @@
+// Client side of virtual-keyboard-unstable-v1, which wayland-protocols
+// doesn't ship. Generated from the copy in `protocols/`.
+
+use std::io::{self, Seek, Write};
+use std::os::fd::AsFd;
+
+use wayland_client::protocol::wl_keyboard::KeymapFormat;
+use xkbcommon::xkb;
+
+pub use self::generated::client;
+use self::generated::client::zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1;
+
+mod generated {
+    #![allow(dead_code, non_camel_case_types, unused_unsafe, unused_variables)]
+    #![allow(non_upper_case_globals, non_snake_case, unused_imports)]
+    #![allow(missing_docs, clippy::all)]
+
+    pub mod client {
+        use wayland_client;
+        use wayland_client::protocol::*;
+
+        pub mod __interfaces {
+            use wayland_client::protocol::__interfaces::*;
+            wayland_scanner::generate_interfaces!("protocols/virtual-keyboard-unstable-v1.xml");
+        }
+        use self::__interfaces::*;
+
+        wayland_scanner::generate_client_code!("protocols/virtual-keyboard-unstable-v1.xml");
+    }
+}
+
+/// Hands `keymap`, an xkb keymap in the text format, to the compositor. It
+/// has to be set before any key or modifier can be sent.
+pub fn upload_keymap(keyboard: &ZwpVirtualKeyboardV1, keymap: &str) -> io::Result<()> {
+    let mut file = tempfile::tempfile()?;
+    // The size includes the terminating nul, like the keymaps compositors send
+    file.write_all(keymap.as_bytes())?;
+    file.write_all(&[0])?;
+    let size = file.stream_position()? as u32;
+    keyboard.keymap(KeymapFormat::XkbV1 as u32, file.as_fd(), size);
+    Ok(())
+}
+
+/// The most characters a keymap from `text_keymap` can have a key for, xkb
+/// keycodes end at 255 and the first 8 are reserved.
+const MAX_TEXT_KEYS: usize = 255 - 8;
+
+/// A keymap with a key for every character of `text`, so any text can be
+/// typed no matter which layout is active, along with the evdev keycodes
+/// that type `text` with it. Newlines and tabs are typed with the enter and
+/// tab keys.
+pub fn text_keymap(text: &str) -> Result<(String, Vec<u32>), String> {
+    let mut chars: Vec<char> = Vec::new();
+    let mut keycodes = Vec::new();
+    for c in text.chars() {
+        let index = chars
+            .iter()
+            .position(|&other| other == c)
+            .unwrap_or_else(|| {
+                chars.push(c);
+                chars.len() - 1
+            });
+        // The first evdev keycode is 1, which is 9 in xkb
+        keycodes.push(index as u32 + 1);
+    }
+    if chars.len() > MAX_TEXT_KEYS {
+        return Err(format!(
+            "can't type more than {MAX_TEXT_KEYS} different characters"
+        ));
+    }
+
+    let mut keycodes_section = String::new();
+    let mut symbols_section = String::new();
+    for (index, c) in chars.iter().enumerate() {
+        let keysym = match c {
+            '\n' => xkb::Keysym::Return,
+            '\t' => xkb::Keysym::Tab,
+            _ => xkb::utf32_to_keysym(*c as u32),
+        };
+        keycodes_section.push_str(&format!("    <K{index}> = {};\n", index + 9));
+        symbols_section.push_str(&format!(
+            "    key <K{index}> {{ [ {} ] }};\n",
+            xkb::keysym_get_name(keysym)
+        ));
+    }
+    let keymap = format!(
+        "xkb_keymap {{
+  xkb_keycodes \"nomouse\" {{
+    minimum = 8;
+    maximum = {};
+{keycodes_section}  }};
+  xkb_types \"nomouse\" {{ include \"complete\" }};
+  xkb_compatibility \"nomouse\" {{ include \"complete\" }};
+  xkb_symbols \"nomouse\" {{
+{symbols_section}  }};
+}};
+",
+        chars.len() + 8,
+    );
+    Ok((keymap, keycodes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_keymap_types_the_text() {
+        let text = "Héllo, wörld!\n\t🙂 Hé";
+        let (keymap, keycodes) = text_keymap(text).unwrap();
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb::Keymap::new_from_string(
+            &context,
+            keymap,
+            xkb::KEYMAP_FORMAT_TEXT_V1,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .expect("keymap compiles");
+        let state = xkb::State::new(&keymap);
+        let typed: Vec<xkb::Keysym> = keycodes
+            .iter()
+            .map(|&keycode| state.key_get_one_sym(xkb::Keycode::new(keycode + 8)))
+            .collect();
+        let expected: Vec<xkb::Keysym> = text
+            .chars()
+            .map(|c| match c {
+                '\n' => xkb::Keysym::Return,
+                '\t' => xkb::Keysym::Tab,
+                _ => xkb::utf32_to_keysym(c as u32),
+            })
+            .collect();
+        assert_eq!(typed, expected);
+        // Characters that repeat share a key
+        assert_eq!(keycodes[1], keycodes[keycodes.len() - 1]);
+    }
+}
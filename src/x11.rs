@@ -0,0 +1,959 @@
+// Shows the overlay on X11, for sessions without a Wayland compositor. Every
+// monitor gets an override redirect window, which the window manager leaves
+// alone, the keyboard is grabbed for typing the labels and the pointer is
+// moved and clicked through the XTEST extension.
+
+use std::{
+    collections::HashSet,
+    env, fmt, mem,
+    os::fd::AsFd,
+    rc::Rc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use cosmic_text::{FontSystem, SwashCache};
+use nix::{
+    errno::Errno,
+    poll::{PollFd, PollFlags, PollTimeout, poll},
+};
+use smithay_client_toolkit::seat::pointer::{BTN_LEFT, BTN_MIDDLE, BTN_RIGHT};
+use wayland_client::protocol::wl_pointer::{Axis, ButtonState};
+use x11rb::{
+    CURRENT_TIME,
+    connection::{Connection, RequestConnection},
+    errors::ReplyOrIdError,
+    protocol::{
+        Event,
+        randr::{self, ConnectionExt as _},
+        shape,
+        xfixes::{self, ConnectionExt as _},
+        xkb::{self, ConnectionExt as _},
+        xproto::{
+            self, AtomEnum, ColormapAlloc, ConnectionExt as _, CreateGCAux, CreateWindowAux,
+            EventMask, GrabMode, GrabStatus, ImageFormat, ImageOrder, KeyButMask, Screen,
+            VisualClass, WindowClass,
+        },
+        xtest::{self, ConnectionExt as _},
+    },
+    rust_connection::RustConnection,
+};
+use xkbcommon::xkb::Keysym;
+
+use crate::{
+    backend::{Backend, OutputInfo, Render},
+    cli::Args,
+    config::Config,
+    keyboard_utils::{Keyboard, KeyboardEvent, Modifiers},
+    output_utils::OutputTarget,
+    pointer::{BackendKind, Extents, PointerBackend},
+    position_selector::{FinalSelector, InitialSelector, SelectorState},
+    session::{self, Outcome, Session},
+};
+
+/// How long to keep trying to grab the keyboard, which the window manager
+/// still holds while the key binding that started nomouse is pressed.
+const GRAB_TIMEOUT: Duration = Duration::from_secs(1);
+const GRAB_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Bytes of a `PutImage` request before the image data.
+const PUT_IMAGE_HEADER_LEN: usize = 24;
+
+/// The lock modifier in the state of key events, the only one that is
+/// locked rather than held.
+const LOCK_MASK: u16 = 0x2;
+
+/// The RMLVO names of the keymap when the X server doesn't tell them.
+const DEFAULT_RULES_NAMES: [&str; 5] = ["evdev", "pc105", "us", "", ""];
+
+/// Whether to show the overlay through X11, because there is no Wayland
+/// compositor to connect to but an X server.
+pub fn is_x11_session() -> bool {
+    env::var_os("WAYLAND_DISPLAY").is_none() && env::var_os("DISPLAY").is_some()
+}
+
+fn x11_error(err: impl fmt::Display) -> String {
+    format!("X11 connection: {err}")
+}
+
+/// Reports a request that failed to go out, there is nothing else to do
+/// about it where events are being sent.
+fn report<T>(result: Result<T, impl fmt::Display>) {
+    if let Err(err) = result {
+        eprintln!("nomouse: {}", x11_error(err));
+    }
+}
+
+/// Moves the pointer through XTEST. Events are sent on `frame` or with the
+/// next request that waits for a reply.
+#[derive(Debug)]
+pub struct XTestPointer {
+    connection: Rc<RustConnection>,
+    root: xproto::Window,
+}
+
+impl XTestPointer {
+    /// Fakes an event of `type_` like `BUTTON_PRESS_EVENT`. `detail` is the
+    /// button or key, or whether a motion is relative.
+    fn fake_input(&self, type_: u8, detail: u8, (x, y): (i32, i32)) {
+        report(self.connection.xtest_fake_input(
+            type_,
+            detail,
+            CURRENT_TIME,
+            self.root,
+            x as i16,
+            y as i16,
+            // The core pointer
+            0,
+        ));
+    }
+}
+
+impl PointerBackend for XTestPointer {
+    fn motion_absolute(&mut self, position: (i32, i32), _extents: Extents) {
+        // Root window coordinates are global already
+        self.fake_input(xproto::MOTION_NOTIFY_EVENT, 0, position);
+    }
+
+    fn motion(&mut self, dx: f64, dy: f64) {
+        let delta = (dx.round() as i32, dy.round() as i32);
+        self.fake_input(xproto::MOTION_NOTIFY_EVENT, 1, delta);
+    }
+
+    fn button(&mut self, button: u32, state: ButtonState) {
+        let button = match button {
+            BTN_LEFT => 1,
+            BTN_MIDDLE => 2,
+            BTN_RIGHT => 3,
+            _ => return,
+        };
+        let type_ = match state {
+            ButtonState::Pressed => xproto::BUTTON_PRESS_EVENT,
+            _ => xproto::BUTTON_RELEASE_EVENT,
+        };
+        self.fake_input(type_, button, (0, 0));
+    }
+
+    fn scroll(&mut self, axis: Axis, direction: i32, _step: f64) {
+        // The wheel is buttons 4 to 7, up, down, left and right
+        let button = match (axis, direction > 0) {
+            (Axis::VerticalScroll, false) => 4,
+            (Axis::VerticalScroll, true) => 5,
+            (_, false) => 6,
+            (_, true) => 7,
+        };
+        for type_ in [xproto::BUTTON_PRESS_EVENT, xproto::BUTTON_RELEASE_EVENT] {
+            self.fake_input(type_, button, (0, 0));
+        }
+    }
+
+    fn frame(&mut self) {
+        report(self.connection.flush());
+    }
+}
+
+/// A RandR monitor, which is usually one output.
+#[derive(Debug, Clone)]
+struct Monitor {
+    name: String,
+    x: i32,
+    y: i32,
+    width: u16,
+    height: u16,
+}
+
+impl Monitor {
+    fn contains(&self, x: i32, y: i32) -> bool {
+        (self.x..self.x + i32::from(self.width)).contains(&x)
+            && (self.y..self.y + i32::from(self.height)).contains(&y)
+    }
+}
+
+/// The overlay on one monitor.
+struct Overlay {
+    window: xproto::Window,
+    gc: xproto::Gcontext,
+    /// Output id of the monitor
+    output: u32,
+    buf: Vec<u8>,
+}
+
+/// The visual the overlay windows are created with, along with its depth
+/// and a colormap for it.
+struct Visual {
+    id: xproto::Visualid,
+    depth: u8,
+    colormap: xproto::Colormap,
+}
+
+/// The X server a `Session` runs on. Outputs are the RandR monitors, their
+/// id is the index in `monitors` plus 1, like registry names start at 1.
+pub struct X11 {
+    connection: Rc<RustConnection>,
+    root: xproto::Window,
+    screen_size: (u16, u16),
+    visual: Visual,
+    keyboard: Keyboard,
+    /// Key repeat rate and delay of the keyboard
+    repeat_info: Option<(i32, i32)>,
+    /// Keycodes that are down, to drop the presses of autorepeat
+    pressed: HashSet<u8>,
+    /// The modifiers last sent as `KeyboardEvent::Modifiers`
+    modifiers: Modifiers,
+    keyboard_events: Vec<KeyboardEvent>,
+    monitors: Vec<Monitor>,
+    overlays: Vec<Overlay>,
+    /// Unmapped while there is nothing to show, the windows are opaque
+    /// without a compositing manager
+    mapped: bool,
+    dirty: bool,
+}
+
+impl X11 {
+    /// Connects to `display`, or to `$DISPLAY` when `None`.
+    pub fn connect(display: Option<&str>) -> Result<Self, String> {
+        let (connection, screen_num) = x11rb::connect(display)
+            .map_err(|err| format!("can't connect to the X server: {err}"))?;
+        let screen = &connection.setup().roots[screen_num];
+        let (root, screen_size) = (
+            screen.root,
+            (screen.width_in_pixels, screen.height_in_pixels),
+        );
+        let (visual_id, depth) = argb_visual(screen);
+        for extension in [
+            xtest::X11_EXTENSION_NAME,
+            xfixes::X11_EXTENSION_NAME,
+            xkb::X11_EXTENSION_NAME,
+        ] {
+            if connection
+                .extension_information(extension)
+                .map_err(x11_error)?
+                .is_none()
+            {
+                return Err(format!("the X server doesn't support {extension}"));
+            }
+        }
+        let (visual, keyboard, repeat_info, monitors) = (|| {
+            // Regions need XFixes 2
+            connection.xfixes_query_version(5, 0)?.reply()?;
+            connection.xkb_use_extension(1, 0)?.reply()?;
+            // Held keys send presses without releases in between, instead
+            // of a release before every press
+            let autorepeat = xkb::PerClientFlag::DETECTABLE_AUTO_REPEAT;
+            connection
+                .xkb_per_client_flags(
+                    xkb::ID::USE_CORE_KBD.into(),
+                    autorepeat,
+                    autorepeat,
+                    0u32.into(),
+                    0u32.into(),
+                    0u32.into(),
+                )?
+                .reply()?;
+            let controls = connection
+                .xkb_get_controls(xkb::ID::USE_CORE_KBD.into())?
+                .reply()?;
+            let repeat_info = (controls.repeat_interval > 0).then(|| {
+                (
+                    1000 / i32::from(controls.repeat_interval),
+                    i32::from(controls.repeat_delay),
+                )
+            });
+            let colormap = connection.generate_id()?;
+            connection.create_colormap(ColormapAlloc::NONE, colormap, root, visual_id)?;
+            let visual = Visual {
+                id: visual_id,
+                depth,
+                colormap,
+            };
+            let keyboard = server_keyboard(&connection, root)?;
+            let monitors = monitors(&connection, root, screen_size)?;
+            Ok::<_, ReplyOrIdError>((visual, keyboard, repeat_info, monitors))
+        })()
+        .map_err(x11_error)?;
+        Ok(Self {
+            connection: Rc::new(connection),
+            root,
+            screen_size,
+            visual,
+            keyboard: keyboard.ok_or("can't compile the keymap of the X server")?,
+            repeat_info,
+            pressed: HashSet::new(),
+            modifiers: Modifiers::default(),
+            keyboard_events: Vec::new(),
+            monitors,
+            overlays: Vec::new(),
+            mapped: false,
+            dirty: false,
+        })
+    }
+
+    fn pointer(&self) -> XTestPointer {
+        XTestPointer {
+            connection: self.connection.clone(),
+            root: self.root,
+        }
+    }
+
+    /// Output ids of the monitors `target` picks.
+    fn target_outputs(&self, target: &OutputTarget) -> Result<Vec<u32>, String> {
+        let all = (1..=self.monitors.len() as u32).collect();
+        match target {
+            OutputTarget::All => Ok(all),
+            // There is no focused monitor on X11, the pointer is the best guess
+            OutputTarget::Focused | OutputTarget::Pointer => {
+                let pointer = self
+                    .connection
+                    .query_pointer(self.root)
+                    .map_err(x11_error)?
+                    .reply()
+                    .map_err(x11_error)?;
+                let (x, y) = (pointer.root_x.into(), pointer.root_y.into());
+                Ok(self
+                    .monitors
+                    .iter()
+                    .position(|monitor| monitor.contains(x, y))
+                    .map_or(all, |i| vec![i as u32 + 1]))
+            }
+            OutputTarget::Name(wanted) => self
+                .monitors
+                .iter()
+                .position(|monitor| monitor.name == *wanted)
+                .map(|i| vec![i as u32 + 1])
+                .ok_or_else(|| format!("no output named {wanted:?}")),
+        }
+    }
+
+    fn monitor(&self, output: u32) -> &Monitor {
+        &self.monitors[output as usize - 1]
+    }
+
+    /// Creates a window covering the monitor of `output`, which clicks
+    /// pass through to the windows below.
+    fn create_overlay(&self, output: u32) -> Result<Overlay, ReplyOrIdError> {
+        let monitor = self.monitor(output);
+        let window = self.connection.generate_id()?;
+        self.connection.create_window(
+            self.visual.depth,
+            window,
+            self.root,
+            monitor.x as i16,
+            monitor.y as i16,
+            monitor.width,
+            monitor.height,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            self.visual.id,
+            &CreateWindowAux::new()
+                .background_pixel(0)
+                .border_pixel(0)
+                .override_redirect(1)
+                .event_mask(EventMask::EXPOSURE)
+                .colormap(self.visual.colormap),
+        )?;
+        // An empty input shape, so the button pressed at the start of a
+        // drag goes to the window below while the overlay is still shown
+        let region = self.connection.generate_id()?;
+        self.connection.xfixes_create_region(region, &[])?;
+        self.connection
+            .xfixes_set_window_shape_region(window, shape::SK::INPUT, 0, 0, region)?;
+        self.connection.xfixes_destroy_region(region)?;
+        let gc = self.connection.generate_id()?;
+        self.connection.create_gc(gc, window, &CreateGCAux::new())?;
+        Ok(Overlay {
+            window,
+            gc,
+            output,
+            buf: vec![0; usize::from(monitor.width) * usize::from(monitor.height) * 4],
+        })
+    }
+
+    /// Sends the key events to us instead of the focused window.
+    fn grab_keyboard(&self) -> Result<(), String> {
+        let start = Instant::now();
+        loop {
+            let status = self
+                .connection
+                .grab_keyboard(
+                    false,
+                    self.root,
+                    CURRENT_TIME,
+                    GrabMode::ASYNC,
+                    GrabMode::ASYNC,
+                )
+                .map_err(x11_error)?
+                .reply()
+                .map_err(x11_error)?
+                .status;
+            match status {
+                GrabStatus::SUCCESS => return Ok(()),
+                GrabStatus::ALREADY_GRABBED if start.elapsed() < GRAB_TIMEOUT => {
+                    thread::sleep(GRAB_INTERVAL)
+                }
+                status => return Err(format!("can't grab the keyboard ({status:?})")),
+            }
+        }
+    }
+
+    fn destroy_overlays(&mut self) {
+        for overlay in self.overlays.drain(..) {
+            report(self.connection.free_gc(overlay.gc));
+            report(self.connection.destroy_window(overlay.window));
+        }
+    }
+
+    /// Draws the premultiplied ARGB8888 buffer of `overlay` onto its
+    /// window, in as many requests as it takes.
+    fn put_image(&self, overlay: &Overlay) -> Result<(), ReplyOrIdError> {
+        let monitor = self.monitor(overlay.output);
+        let stride = usize::from(monitor.width) * 4;
+        let max_len = self.connection.maximum_request_bytes() - PUT_IMAGE_HEADER_LEN;
+        let rows = (max_len / stride).max(1);
+        let msb_first = self.connection.setup().image_byte_order == ImageOrder::MSB_FIRST;
+        for (i, chunk) in overlay.buf.chunks(stride * rows).enumerate() {
+            let mut data = chunk.to_vec();
+            if msb_first {
+                for pixel in data.chunks_exact_mut(4) {
+                    pixel.reverse();
+                }
+            }
+            self.connection.put_image(
+                ImageFormat::Z_PIXMAP,
+                overlay.window,
+                overlay.gc,
+                monitor.width,
+                (chunk.len() / stride) as u16,
+                0,
+                (i * rows) as i16,
+                0,
+                self.visual.depth,
+                &data,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The keycodes of `held`, to press and release through XTEST.
+    fn modifier_keycodes(&self, held: Modifiers) -> Vec<u8> {
+        [
+            (held.shift, Keysym::Shift_L),
+            (held.ctrl, Keysym::Control_L),
+            (held.alt, Keysym::Alt_L),
+            (held.logo, Keysym::Super_L),
+        ]
+        .into_iter()
+        .filter(|(set, _)| *set)
+        .filter_map(|(_, keysym)| self.keyboard.code_of(keysym))
+        .map(|code| (code + 8) as u8)
+        .collect()
+    }
+
+    fn handle_key(&mut self, keycode: u8, state: KeyButMask, pressed: bool) {
+        // X keycodes are offset by 8 from the evdev ones, like xkb ones
+        let code = u32::from(keycode) - 8;
+        let state = u16::from(state);
+        // The modifiers from before the event, like the modifiers that come
+        // before a key on Wayland
+        self.keyboard.update_modifiers(
+            u32::from(state & 0xff & !LOCK_MASK),
+            0,
+            u32::from(state & LOCK_MASK),
+            u32::from(state >> 13 & 0x3),
+        );
+        let key = self.keyboard.key(code, pressed);
+        self.keyboard.update_key(code, pressed);
+        self.keyboard_events.push(KeyboardEvent::Key(key));
+        let modifiers = self.keyboard.modifiers();
+        if modifiers != self.modifiers {
+            self.modifiers = modifiers;
+            self.keyboard_events
+                .push(KeyboardEvent::Modifiers(modifiers));
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            // Not again while held, normal mode times repeats by itself
+            Event::KeyPress(event) if self.pressed.insert(event.detail) => {
+                self.handle_key(event.detail, event.state, true);
+            }
+            Event::KeyRelease(event) => {
+                self.pressed.remove(&event.detail);
+                self.handle_key(event.detail, event.state, false);
+            }
+            // Only the last of a series of exposures, the whole window is
+            // drawn again anyway
+            Event::Expose(event) if event.count == 0 => self.dirty = true,
+            Event::Error(err) => eprintln!("nomouse: X11 error: {err:?}"),
+            _ => {}
+        }
+    }
+
+    /// Waits for events until `timeout` is up and handles them.
+    pub fn dispatch(&mut self, timeout: PollTimeout) -> Result<(), String> {
+        self.connection.flush().map_err(x11_error)?;
+        let mut event = self.connection.poll_for_event().map_err(x11_error)?;
+        if event.is_none() {
+            let mut fds = [PollFd::new(
+                self.connection.stream().as_fd(),
+                PollFlags::POLLIN,
+            )];
+            match poll(&mut fds, timeout) {
+                Ok(_) | Err(Errno::EINTR) => {}
+                Err(err) => return Err(format!("poll: {err}")),
+            }
+            event = self.connection.poll_for_event().map_err(x11_error)?;
+        }
+        while let Some(next) = event {
+            self.handle_event(next);
+            event = self.connection.poll_for_event().map_err(x11_error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Backend for X11 {
+    /// XTEST is the only way to move the pointer on X11, whatever `kind`.
+    fn take_pointer(&mut self, _kind: BackendKind) -> Result<Box<dyn PointerBackend>, String> {
+        Ok(Box::new(self.pointer()))
+    }
+
+    fn keep_pointer(&mut self, _kind: BackendKind, _pointer: Box<dyn PointerBackend>) {}
+
+    fn show(&mut self, target: &OutputTarget) -> Result<(), String> {
+        for output in self.target_outputs(target)? {
+            let overlay = self.create_overlay(output).map_err(x11_error)?;
+            self.overlays.push(overlay);
+        }
+        for overlay in &self.overlays {
+            report(self.connection.map_window(overlay.window));
+        }
+        self.mapped = true;
+        self.dirty = true;
+        if let Err(err) = self.grab_keyboard() {
+            self.destroy_overlays();
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    fn hide(&mut self) {
+        self.destroy_overlays();
+        report(self.connection.ungrab_keyboard(CURRENT_TIME));
+        self.sync();
+    }
+
+    fn is_shown(&self) -> bool {
+        !self.overlays.is_empty()
+    }
+
+    fn take_keyboard_events(&mut self) -> Vec<KeyboardEvent> {
+        mem::take(&mut self.keyboard_events)
+    }
+
+    /// Monitors are only looked up when connecting.
+    fn take_layout_changed(&mut self) -> bool {
+        false
+    }
+
+    fn create_selector(&self, config: &Config) -> Result<SelectorState, String> {
+        let grids = self
+            .overlays
+            .iter()
+            .map(|overlay| {
+                let monitor = self.monitor(overlay.output);
+                InitialSelector::new(
+                    overlay.output,
+                    &config.hints,
+                    &config.grid,
+                    monitor.width.into(),
+                    monitor.height.into(),
+                )
+            })
+            .collect();
+        SelectorState::new(grids, &config.hints.alphabet)
+    }
+
+    fn global_position(&self, selector: &FinalSelector) -> Option<(i32, i32)> {
+        let monitor = self
+            .monitors
+            .get((selector.output as usize).checked_sub(1)?)?;
+        Some((
+            monitor.x + (selector.x + selector.width / 2) as i32,
+            monitor.y + (selector.y + selector.height / 2) as i32,
+        ))
+    }
+
+    fn layout_extents(&self) -> Extents {
+        let (width, height) = self.screen_size;
+        (0, 0, width.into(), height.into())
+    }
+
+    fn outputs(&self) -> Vec<OutputInfo> {
+        self.monitors
+            .iter()
+            .zip(1..)
+            .map(|(monitor, id)| OutputInfo {
+                id,
+                name: Some(monitor.name.clone()),
+                rect: (
+                    monitor.x,
+                    monitor.y,
+                    monitor.width.into(),
+                    monitor.height.into(),
+                ),
+                shown: self.overlays.iter().any(|overlay| overlay.output == id),
+            })
+            .collect()
+    }
+
+    fn repeat_info(&self) -> Option<(i32, i32)> {
+        self.repeat_info
+    }
+
+    fn press_held_modifiers(&mut self, held: Modifiers) {
+        let pointer = self.pointer();
+        for keycode in self.modifier_keycodes(held) {
+            pointer.fake_input(xproto::KEY_PRESS_EVENT, keycode, (0, 0));
+        }
+        self.flush();
+    }
+
+    fn release_held_modifiers(&mut self, held: Modifiers) {
+        let pointer = self.pointer();
+        for keycode in self.modifier_keycodes(held) {
+            pointer.fake_input(xproto::KEY_RELEASE_EVENT, keycode, (0, 0));
+        }
+        self.flush();
+    }
+
+    fn type_text(&mut self, _text: &str) -> Result<(), String> {
+        Err("--type isn't supported on X11".to_string())
+    }
+
+    fn request_redraw(&mut self) {
+        self.dirty = true;
+    }
+
+    fn draw(&mut self, render: Option<&mut Render>) {
+        let Some(render) = render else {
+            if self.mapped {
+                for overlay in &self.overlays {
+                    report(self.connection.unmap_window(overlay.window));
+                }
+                self.mapped = false;
+                self.flush();
+            }
+            return;
+        };
+        if !self.mapped {
+            for overlay in &self.overlays {
+                report(self.connection.map_window(overlay.window));
+            }
+            self.mapped = true;
+        }
+        if !self.dirty {
+            return;
+        }
+        let mut overlays = mem::take(&mut self.overlays);
+        for overlay in &mut overlays {
+            let monitor = self.monitor(overlay.output);
+            let (width, height) = (monitor.width.into(), monitor.height.into());
+            render(overlay.output, &mut overlay.buf, width, height, 1.0);
+            report(self.put_image(overlay));
+        }
+        self.overlays = overlays;
+        self.dirty = false;
+        self.flush();
+    }
+
+    fn flush(&mut self) {
+        report(self.connection.flush());
+    }
+
+    fn sync(&mut self) {
+        let reply = self
+            .connection
+            .get_input_focus()
+            .map_err(ReplyOrIdError::from)
+            .and_then(|cookie| Ok(cookie.reply()?));
+        report(reply);
+    }
+}
+
+/// A visual with an alpha channel for compositing managers to blend the
+/// windows with, or the root visual when the server has none. Along with
+/// its depth.
+fn argb_visual(screen: &Screen) -> (xproto::Visualid, u8) {
+    screen
+        .allowed_depths
+        .iter()
+        .filter(|depth| depth.depth == 32)
+        .flat_map(|depth| &depth.visuals)
+        .find(|visual| visual.class == VisualClass::TRUE_COLOR && visual.red_mask == 0xff0000)
+        .map_or((screen.root_visual, screen.root_depth), |visual| {
+            (visual.visual_id, 32)
+        })
+}
+
+/// The keyboard of the X server, compiled from the RMLVO names it was set
+/// up with. `None` when xkb can't compile them.
+fn server_keyboard(
+    connection: &RustConnection,
+    root: xproto::Window,
+) -> Result<Option<Keyboard>, ReplyOrIdError> {
+    let atom = connection
+        .intern_atom(false, b"_XKB_RULES_NAMES")?
+        .reply()?
+        .atom;
+    let property = connection
+        .get_property(false, root, atom, AtomEnum::STRING, 0, 1024)?
+        .reply()?;
+    let value = String::from_utf8_lossy(&property.value);
+    let mut names: Vec<&str> = value.split('\0').collect();
+    names.resize(DEFAULT_RULES_NAMES.len(), "");
+    for (name, default) in names.iter_mut().zip(DEFAULT_RULES_NAMES) {
+        if name.is_empty() {
+            *name = default;
+        }
+    }
+    let options = (!names[4].is_empty()).then(|| names[4].to_string());
+    Ok(Keyboard::from_names(
+        names[0], names[1], names[2], names[3], options,
+    ))
+}
+
+/// The monitors of the screen from left to right, or the whole screen as
+/// one monitor when the server doesn't support RandR 1.5.
+fn monitors(
+    connection: &RustConnection,
+    root: xproto::Window,
+    (width, height): (u16, u16),
+) -> Result<Vec<Monitor>, ReplyOrIdError> {
+    let whole_screen = vec![Monitor {
+        name: "screen".to_string(),
+        x: 0,
+        y: 0,
+        width,
+        height,
+    }];
+    if connection
+        .extension_information(randr::X11_EXTENSION_NAME)?
+        .is_none()
+    {
+        return Ok(whole_screen);
+    }
+    let version = connection.randr_query_version(1, 5)?.reply()?;
+    if (version.major_version, version.minor_version) < (1, 5) {
+        return Ok(whole_screen);
+    }
+    let mut monitors = Vec::new();
+    for info in connection.randr_get_monitors(root, true)?.reply()?.monitors {
+        let name = connection.get_atom_name(info.name)?.reply()?.name;
+        monitors.push(Monitor {
+            name: String::from_utf8_lossy(&name).into_owned(),
+            x: info.x.into(),
+            y: info.y.into(),
+            width: info.width,
+            height: info.height,
+        });
+    }
+    if monitors.is_empty() {
+        return Ok(whole_screen);
+    }
+    monitors.sort_by_key(|monitor| (monitor.x, monitor.y));
+    Ok(monitors)
+}
+
+/// Shows the overlay until the selection is done and acts on it.
+fn select(x11: &mut X11, args: Args, config: Config) -> Result<Outcome, String> {
+    let mut font_system = FontSystem::new();
+    let mut swash_cache = SwashCache::new();
+    let mut session = Session::start(x11, args, config)?;
+    loop {
+        if let Some(outcome) = session.update(x11, &mut font_system, &mut swash_cache) {
+            session.end(x11);
+            return Ok(outcome);
+        }
+        x11.dispatch(session::poll_timeout(session.next_update()))?;
+    }
+}
+
+/// Shows the overlay on `$DISPLAY` once, like `main::run` does on Wayland.
+pub fn run(args: Args, config: Config) -> Result<Outcome, String> {
+    let mut x11 = X11::connect(None)?;
+    if args.repeat_last {
+        return session::repeat_last(&mut x11, &config);
+    }
+    if args.text.is_some() {
+        return Err("--type isn't supported on X11".to_string());
+    }
+    select(&mut x11, args, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::Path,
+        process::{Child, Command, Stdio},
+    };
+
+    use super::*;
+    use x11rb::NONE;
+
+    use crate::{cli::Mode, headless::selector_after};
+
+    const WIDTH: u16 = 640;
+    const HEIGHT: u16 = 360;
+
+    /// An Xvfb server on a display of its own, killed when dropped.
+    struct Xvfb {
+        process: Child,
+        display: String,
+    }
+
+    impl Xvfb {
+        /// Starts Xvfb on the first free display from :99.
+        fn start() -> Self {
+            let number = (99..200)
+                .find(|number| !Path::new(&format!("/tmp/.X11-unix/X{number}")).exists())
+                .expect("a free display");
+            let display = format!(":{number}");
+            let process = Command::new("Xvfb")
+                .args([&display, "-screen", "0", &format!("{WIDTH}x{HEIGHT}x24")])
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("Xvfb is installed");
+            let xvfb = Self { process, display };
+            let start = Instant::now();
+            while x11rb::connect(Some(&xvfb.display)).is_err() {
+                assert!(
+                    start.elapsed() < Duration::from_secs(5),
+                    "Xvfb didn't start"
+                );
+                thread::sleep(Duration::from_millis(20));
+            }
+            xvfb
+        }
+    }
+
+    impl Drop for Xvfb {
+        fn drop(&mut self) {
+            let _ = self.process.kill();
+            let _ = self.process.wait();
+        }
+    }
+
+    /// A window of another client covering the screen, which records what
+    /// the pointer does to it.
+    fn client_window(connection: &RustConnection, screen_num: usize) -> xproto::Window {
+        let screen = &connection.setup().roots[screen_num];
+        let window = connection.generate_id().unwrap();
+        let events =
+            EventMask::BUTTON_PRESS | EventMask::BUTTON_RELEASE | EventMask::POINTER_MOTION;
+        connection
+            .create_window(
+                screen.root_depth,
+                window,
+                screen.root,
+                0,
+                0,
+                WIDTH,
+                HEIGHT,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                screen.root_visual,
+                &CreateWindowAux::new().event_mask(events),
+            )
+            .unwrap();
+        connection.map_window(window).unwrap();
+        connection.get_input_focus().unwrap().reply().unwrap();
+        window
+    }
+
+    /// Types `keys` with XTEST once the overlay has grabbed the keyboard.
+    fn type_keys(display: &str, keys: &str) {
+        let (connection, screen_num) = x11rb::connect(Some(display)).unwrap();
+        let root = connection.setup().roots[screen_num].root;
+        let start = Instant::now();
+        loop {
+            let grab = connection
+                .grab_keyboard(false, root, CURRENT_TIME, GrabMode::ASYNC, GrabMode::ASYNC)
+                .unwrap()
+                .reply()
+                .unwrap();
+            if grab.status == GrabStatus::ALREADY_GRABBED {
+                break;
+            }
+            connection.ungrab_keyboard(CURRENT_TIME).unwrap();
+            assert!(start.elapsed() < Duration::from_secs(5), "no overlay");
+            thread::sleep(Duration::from_millis(20));
+        }
+        let keyboard = server_keyboard(&connection, root).unwrap().unwrap();
+        for key in keys.chars() {
+            let code = (0..248).find(|&code| keyboard.key_char(code) == Some(key));
+            let keycode = (code.unwrap() + 8) as u8;
+            for type_ in [xproto::KEY_PRESS_EVENT, xproto::KEY_RELEASE_EVENT] {
+                connection
+                    .xtest_fake_input(type_, keycode, CURRENT_TIME, NONE, 0, 0, 0)
+                    .unwrap();
+            }
+        }
+        connection.get_input_focus().unwrap().reply().unwrap();
+    }
+
+    /// The center of the cell `keys` select on the whole screen.
+    fn cell_center(keys: &str) -> (i32, i32) {
+        let config = Config::default();
+        let SelectorState::Final(selector) =
+            selector_after(keys, WIDTH.into(), HEIGHT.into(), &config)
+        else {
+            panic!("{keys:?} isn't a position");
+        };
+        (
+            (selector.x + selector.width / 2) as i32,
+            (selector.y + selector.height / 2) as i32,
+        )
+    }
+
+    #[test]
+    #[ignore = "needs Xvfb"]
+    fn drags_on_xvfb() {
+        let xvfb = Xvfb::start();
+        let (client, screen_num) = x11rb::connect(Some(&xvfb.display)).unwrap();
+        let window = client_window(&client, screen_num);
+
+        let mut x11 = X11::connect(Some(&xvfb.display)).unwrap();
+        let args = Args {
+            mode: Mode::Drag,
+            ..Default::default()
+        };
+        let display = xvfb.display.clone();
+        let typist = thread::spawn(move || {
+            type_keys(&display, "was");
+            // After the button was pressed and the grid started over
+            thread::sleep(Duration::from_millis(500));
+            type_keys(&display, "dls");
+        });
+        let outcome = select(&mut x11, args, Config::default()).unwrap();
+        typist.join().unwrap();
+        assert_eq!(outcome.status, 0);
+
+        let pointer = client.query_pointer(window).unwrap().reply().unwrap();
+        let expected = cell_center("dls");
+        assert_eq!((pointer.root_x.into(), pointer.root_y.into()), expected);
+
+        // The overlay let the button and the motion through to the window
+        // below
+        let (mut pressed, mut moved) = (false, false);
+        while let Some(event) = client.poll_for_event().unwrap() {
+            match event {
+                Event::ButtonPress(event) if event.event == window => pressed = true,
+                Event::MotionNotify(event) if event.event == window => moved = true,
+                _ => {}
+            }
+        }
+        assert!(pressed, "the window didn't get the button press");
+        assert!(moved, "the window didn't get the pointer motion");
+    }
+}